@@ -0,0 +1,123 @@
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use serde_json::json;
+use crate::config::BackendConfig;
+
+const ANILIST_URL: &str = "https://graphql.anilist.co";
+
+const VIEWER_QUERY: &str = "query { Viewer { id } }";
+
+const LIST_QUERY: &str = "query ($userId: Int) {
+  MediaListCollection(userId: $userId, type: ANIME, status_in: [CURRENT, PLANNING]) {
+    lists { entries { media { title { romaji english } } } }
+  }
+}";
+
+/// A title on the user's Watching or Planning list.
+#[derive(Debug, Clone)]
+pub struct ListEntry {
+    pub romaji: String,
+    pub english: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct GraphQlResponse<T> {
+    data: Option<T>,
+    #[serde(default)]
+    errors: Vec<GraphQlError>,
+}
+
+#[derive(Deserialize)]
+struct GraphQlError {
+    message: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ViewerData {
+    viewer: Viewer,
+}
+
+#[derive(Deserialize)]
+struct Viewer {
+    id: i64,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ListData {
+    media_list_collection: Collection,
+}
+
+#[derive(Deserialize)]
+struct Collection {
+    lists: Vec<List>,
+}
+
+#[derive(Deserialize)]
+struct List {
+    entries: Vec<Entry>,
+}
+
+#[derive(Deserialize)]
+struct Entry {
+    media: Media,
+}
+
+#[derive(Deserialize)]
+struct Media {
+    title: MediaTitle,
+}
+
+#[derive(Deserialize)]
+struct MediaTitle {
+    romaji: Option<String>,
+    english: Option<String>,
+}
+
+pub struct AniListClient {
+    client: reqwest::Client,
+    token: String,
+}
+
+impl AniListClient {
+    /// Uses the backend's proxy, so the sync works wherever the backend does.
+    pub fn new(token: &str, backend: &BackendConfig) -> Result<Self> {
+        let builder = reqwest::Client::builder().timeout(std::time::Duration::from_secs(15));
+        let client = crate::api::with_proxy(builder, backend)?
+            .build()
+            .context("Failed to build HTTP client")?;
+        Ok(Self { client, token: token.trim().to_string() })
+    }
+
+    /// Titles on the authenticated user's Watching and Planning lists.
+    pub async fn watchlist(&self) -> Result<Vec<ListEntry>> {
+        let viewer: ViewerData = self.query(VIEWER_QUERY, json!({})).await?;
+        let lists: ListData = self.query(LIST_QUERY, json!({ "userId": viewer.viewer.id })).await?;
+
+        Ok(lists.media_list_collection.lists.into_iter()
+            .flat_map(|l| l.entries)
+            .filter_map(|e| {
+                let MediaTitle { romaji, english } = e.media.title;
+                let romaji = romaji.or_else(|| english.clone())?;
+                Some(ListEntry { romaji, english })
+            })
+            .collect())
+    }
+
+    async fn query<T: for<'de> Deserialize<'de>>(&self, query: &str, variables: serde_json::Value) -> Result<T> {
+        let resp = self.client.post(ANILIST_URL)
+            .bearer_auth(&self.token)
+            .json(&json!({ "query": query, "variables": variables }))
+            .send().await?;
+        let status = resp.status();
+        let body: GraphQlResponse<T> = resp.json().await.context("Failed to parse AniList response")?;
+        if let Some(err) = body.errors.first() {
+            bail!("AniList: {}", err.message);
+        }
+        if !status.is_success() {
+            bail!("AniList returned {}", status);
+        }
+        body.data.context("AniList response had no data")
+    }
+}
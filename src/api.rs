@@ -1,17 +1,78 @@
-use anyhow::{Context, Result, bail};
 use regex::Regex;
 use reqwest::header::{HeaderMap, HeaderValue, USER_AGENT, REFERER, ORIGIN};
+use reqwest::{StatusCode, Url};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use std::sync::OnceLock;
+use serde_json::Value;
+use crate::config::BackendConfig;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, OnceLock};
 
 static SLUG_RE: OnceLock<Regex> = OnceLock::new();
 static URL_RE: OnceLock<Regex> = OnceLock::new();
 static KWIK_URL_RE: OnceLock<Regex> = OnceLock::new();
 static PACKER_RE: OnceLock<Regex> = OnceLock::new();
 static EVAL_RE: OnceLock<Regex> = OnceLock::new();
-static M3U8_RE: OnceLock<Regex> = OnceLock::new();
+static STREAM_LINK_RE: OnceLock<Regex> = OnceLock::new();
 static WORD_RE: OnceLock<Regex> = OnceLock::new();
 
+/// Why an [`AnimeClient`] call failed, so callers can tell a dead connection from a changed
+/// backend without matching on message text. `Display` is the message shown to the user.
+#[derive(Debug, thiserror::Error)]
+pub enum ApiError {
+    /// Unusable client settings: a header, proxy, base URL or rate limit.
+    #[error("{0}")]
+    Config(String),
+    /// No response came back: the connection failed or timed out. `message` carries the
+    /// underlying cause, so it survives being shown with plain `{}`.
+    #[error("{message}")]
+    Network { message: String },
+    /// The backend answered with an error status, or with something other than JSON.
+    #[error("{message}")]
+    Upstream { status: u16, message: String },
+    /// Cloudflare served its challenge page instead of the content.
+    #[error("{}", CLOUDFLARE_BLOCKED)]
+    Blocked,
+    /// The response wasn't shaped as expected, usually because the backend changed.
+    #[error("{0}")]
+    Parse(String),
+    /// The kwik pages came back but no stream URL could be dug out of them.
+    #[error("{0}")]
+    ExtractionFailed(String),
+    /// Reading or clearing the episode cache failed.
+    #[error("{message}")]
+    Io {
+        message: String,
+        #[source]
+        source: std::io::Error,
+    },
+}
+
+impl ApiError {
+    /// Prefixes the message of a request that failed every retry.
+    fn after_retries(self, attempts: usize) -> Self {
+        let prefix = |message: String| format!("Giving up after {} attempts: {}", attempts, message);
+        match self {
+            ApiError::Network { message } => ApiError::Network { message: prefix(message) },
+            ApiError::Upstream { status, message } => ApiError::Upstream { status, message: prefix(message) },
+            other => other,
+        }
+    }
+}
+
+pub type Result<T, E = ApiError> = std::result::Result<T, E>;
+
+/// `e` and each of its causes, joined like anyhow's `{:#}`.
+fn error_chain(e: &dyn std::error::Error) -> String {
+    let mut message = e.to_string();
+    let mut source = e.source();
+    while let Some(cause) = source {
+        message.push_str(&format!(": {}", cause));
+        source = cause.source();
+    }
+    message
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct SearchResponse {
     pub data: Vec<Anime>,
@@ -32,6 +93,36 @@ pub struct Anime {
     pub anime_type: Option<String>,
 }
 
+/// Extra details from the `info` endpoint, which search results don't carry.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct AnimeInfo {
+    #[serde(alias = "description")]
+    pub synopsis: Option<String>,
+    pub genres: Vec<String>,
+    pub studio: Option<String>,
+}
+
+impl AnimeInfo {
+    pub fn is_empty(&self) -> bool {
+        self.synopsis.is_none() && self.genres.is_empty() && self.studio.is_none()
+    }
+}
+
+/// A release from the `airing` endpoint: one entry per new episode, so a show can repeat.
+#[derive(Debug, Deserialize)]
+struct AiringRelease {
+    #[serde(default)]
+    anime_id: u32,
+    anime_title: String,
+    anime_session: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct AiringResponse {
+    data: Vec<AiringRelease>,
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct SeriesResponse {
     pub title: String,
@@ -46,6 +137,11 @@ pub struct Episode {
     pub episode: String,
     pub session: String,
     pub snapshot: String,
+    #[serde(default)]
+    pub title: Option<String>,
+    /// Upload timestamp, e.g. `2023-04-12 15:30:02`.
+    #[serde(default)]
+    pub created_at: Option<String>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -54,66 +150,336 @@ pub struct StreamItem {
     pub name: String,
 }
 
+const DEFAULT_TIMEOUT_SECS: u64 = 15;
+const DEFAULT_REQUESTS_PER_SEC: f64 = 4.0;
+const CONNECT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Delays before each retry of a backend request; one retry per entry.
+const RETRY_BACKOFF_MS: &[u64] = &[200, 400, 800];
+
+const CLOUDFLARE_BLOCKED: &str = "Blocked by Cloudflare — try again later or update the User-Agent (backend.user_agent in config.toml)";
+/// Strings only found on Cloudflare's challenge and block pages.
+const CLOUDFLARE_MARKERS: &[&str] = &["cf-browser-verification", "Just a moment...", "challenge-platform", "cf-chl-", "Attention Required! | Cloudflare"];
+
+const DEFAULT_BASE_URL: &str = "https://anime.apex-cloud.workers.dev";
+const DEFAULT_USER_AGENT: &str = "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36";
+const DEFAULT_ORIGIN: &str = "https://www.animepah.me";
+const DEFAULT_REFERER: &str = "https://www.animepah.me/";
+
+/// Cheap to clone: clones share the connection pool and the `last_request` debug slot,
+/// so a copy can be moved into a spawned task.
+#[derive(Clone)]
 pub struct AnimeClient {
     client: reqwest::Client,
-    base_url: &'static str,
+    base_url: Url,
+    timeout: std::time::Duration,
+    /// Proxy in use (credentials stripped), named in connection errors.
+    proxy: Option<String>,
+    last_request: Arc<Mutex<Option<String>>>,
+    episode_cache: Option<EpisodeCache>,
+    throttle: Option<Throttle>,
+}
+
+/// Spaces backend requests at least `interval` apart. The gate is shared by every clone of
+/// the client, so background prefetches and batch downloads queue behind each other too.
+#[derive(Clone)]
+struct Throttle {
+    interval: std::time::Duration,
+    next: Arc<tokio::sync::Mutex<std::time::Instant>>,
+}
+
+impl Throttle {
+    async fn wait(&self) {
+        let mut next = self.next.lock().await;
+        let now = std::time::Instant::now();
+        if *next > now {
+            tokio::time::sleep(*next - now).await;
+        }
+        *next = (*next).max(now) + self.interval;
+    }
+}
+
+/// Episode pages saved as JSON files, reused until they are `ttl` old.
+#[derive(Clone)]
+struct EpisodeCache {
+    dir: PathBuf,
+    ttl: std::time::Duration,
+}
+
+impl EpisodeCache {
+    fn path(&self, session: &str, page: u32) -> PathBuf {
+        let session: String = session.chars().filter(|c| c.is_ascii_alphanumeric() || *c == '-').collect();
+        self.dir.join(format!("{}-{}.json", session, page))
+    }
+
+    /// The cached page, unless it is missing, expired or unreadable.
+    fn load(&self, session: &str, page: u32) -> Option<SeriesResponse> {
+        let path = self.path(session, page);
+        let age = std::fs::metadata(&path).ok()?.modified().ok()?.elapsed().ok()?;
+        if age >= self.ttl { return None; }
+        serde_json::from_str(&std::fs::read_to_string(path).ok()?).ok()
+    }
+
+    /// Best effort: a cache that can't be written just means fetching again next time.
+    /// Empty pages are not kept, so an upcoming show's first episodes appear as soon as
+    /// they are out rather than after the TTL.
+    fn store(&self, session: &str, page: u32, res: &SeriesResponse) {
+        if res.episodes.is_empty() { return; }
+        if std::fs::create_dir_all(&self.dir).is_err() { return; }
+        if let Ok(json) = serde_json::to_string(res) {
+            let _ = std::fs::write(self.path(session, page), json);
+        }
+    }
 }
 
 impl AnimeClient {
-    pub fn new() -> Result<Self> {
+    /// Builds a client for the configured backend; unset fields keep the built-in defaults.
+    pub fn new(backend: &BackendConfig) -> Result<Self> {
+        let header = |value: &Option<String>, default: &str, name: &str| -> Result<HeaderValue> {
+            let value = value.as_deref().unwrap_or(default);
+            HeaderValue::from_str(value).map_err(|e| ApiError::Config(format!("Invalid {} header '{}': {}", name, value, e)))
+        };
         let mut headers = HeaderMap::new();
-        headers.insert(USER_AGENT, HeaderValue::from_static("Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36"));
-        headers.insert(ORIGIN, HeaderValue::from_static("https://www.animepah.me"));
-        headers.insert(REFERER, HeaderValue::from_static("https://www.animepah.me/"));
-        
-        let client = reqwest::Client::builder()
+        headers.insert(USER_AGENT, header(&backend.user_agent, DEFAULT_USER_AGENT, "user_agent")?);
+        headers.insert(ORIGIN, header(&backend.origin, DEFAULT_ORIGIN, "origin")?);
+        headers.insert(REFERER, header(&backend.referer, DEFAULT_REFERER, "referer")?);
+
+        let timeout = std::time::Duration::from_secs(backend.timeout_secs.filter(|&t| t > 0).unwrap_or(DEFAULT_TIMEOUT_SECS));
+        let builder = reqwest::Client::builder()
             .default_headers(headers)
-            .build()
-            .context("Failed to build HTTP client")?;
+            .timeout(timeout)
+            .connect_timeout(CONNECT_TIMEOUT.min(timeout));
+        let builder = with_proxy(builder, backend)?;
+
+        let base_url = normalize_base_url(backend.base_url.as_deref().unwrap_or(DEFAULT_BASE_URL))?;
+        let proxy = match configured_proxy(backend) {
+            Some(url) => Some(url.to_string()),
+            // Setting any proxy on the builder turns off reqwest's own reading of the proxy
+            // variables, NO_PROXY included, so leave that to reqwest and only work out which
+            // proxy it will pick, to name it in connection errors.
+            None => env_proxy(&base_url, |name| std::env::var(name).ok()),
+        };
+        let client = builder.build().map_err(|e| ApiError::Config(format!("Failed to build HTTP client: {}", e)))?;
+
+        let per_sec = backend.requests_per_sec.unwrap_or(DEFAULT_REQUESTS_PER_SEC);
+        if !per_sec.is_finite() || per_sec < 0.0 {
+            return Err(ApiError::Config(format!("Invalid requests_per_sec {}: expected a positive number, or 0 for no limit", per_sec)));
+        }
+        let throttle = (per_sec > 0.0).then(|| Throttle {
+            interval: std::time::Duration::from_secs_f64(1.0 / per_sec),
+            next: Arc::new(tokio::sync::Mutex::new(std::time::Instant::now())),
+        });
 
         Ok(Self {
             client,
-            base_url: "https://anime.apex-cloud.workers.dev",
+            base_url,
+            timeout,
+            proxy: proxy.map(|p| redact_proxy(&p)),
+            last_request: Arc::new(Mutex::new(None)),
+            episode_cache: None,
+            throttle,
         })
     }
 
+    /// Keeps episode pages in `dir` and serves them from there for `ttl`.
+    pub fn with_episode_cache(mut self, dir: PathBuf, ttl: std::time::Duration) -> Self {
+        self.episode_cache = Some(EpisodeCache { dir, ttl });
+        self
+    }
+
+    /// Deletes every cached episode page, returning how many there were.
+    pub fn clear_episode_cache(&self) -> Result<usize> {
+        let Some(cache) = &self.episode_cache else { return Ok(0) };
+        let entries = match std::fs::read_dir(&cache.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(source) => return Err(ApiError::Io { message: format!("Failed to read {}", cache.dir.display()), source }),
+        };
+        let mut removed = 0;
+        for entry in entries.flatten() {
+            if entry.path().extension().is_some_and(|ext| ext == "json") {
+                std::fs::remove_file(entry.path())
+                    .map_err(|source| ApiError::Io { message: format!("Failed to remove {}", entry.path().display()), source })?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    /// URL of the most recently started request, for the debug HUD.
+    pub fn last_request(&self) -> Option<String> {
+        self.last_request.lock().ok().and_then(|r| r.clone())
+    }
+
+    /// Turns a timeout into a readable message and blames the proxy for connection failures
+    /// when one is in use; other errors keep reqwest's own message and its causes.
+    fn request_error(&self, e: reqwest::Error) -> ApiError {
+        let message = if let Some(proxy) = self.proxy.as_deref().filter(|_| e.is_connect()) {
+            let problem = if e.is_timeout() { "did not answer" } else { "refused or failed the connection" };
+            format!("Proxy {} {}: {}", proxy, problem, error_chain(&e))
+        } else if e.is_connect() && e.is_timeout() {
+            format!("Could not connect within {}s", CONNECT_TIMEOUT.min(self.timeout).as_secs())
+        } else if e.is_timeout() {
+            format!("Request timed out after {}s", self.timeout.as_secs())
+        } else {
+            format!("Request failed: {}", error_chain(&e))
+        };
+        ApiError::Network { message }
+    }
+
+    /// Starts a GET for `url` once the throttle lets it through. Every request goes through
+    /// here, so kwik page fetches are spaced out like backend calls.
+    async fn get(&self, url: &str) -> reqwest::RequestBuilder {
+        if let Some(throttle) = &self.throttle {
+            throttle.wait().await;
+        }
+        if let Ok(mut last) = self.last_request.lock() {
+            *last = Some(url.to_string());
+        }
+        self.client.get(url)
+    }
+
     pub async fn search(&self, query: &str) -> Result<SearchResponse> {
-        let url = format!("{}/?method=search&query={}", self.base_url, urlencoding::encode(query));
-        let resp = self.client.get(&url).send().await?;
-        resp.json::<SearchResponse>().await.context("Failed to parse search response")
+        self.search_page(query, 1).await
+    }
+
+    /// One page of search results; `last_page` says how many there are.
+    pub async fn search_page(&self, query: &str, page: u32) -> Result<SearchResponse> {
+        let url = self.endpoint(&[("method", "search"), ("query", query), ("page", &page.to_string())]);
+        self.fetch_json(url.as_str(), "search", &["data", "last_page", "current_page"]).await
+    }
+
+    /// Shows with new episodes out, most recent release first, from the backend's `airing`
+    /// method. Not every backend has this endpoint.
+    pub async fn get_popular(&self) -> Result<Vec<Anime>> {
+        let url = self.endpoint(&[("method", "airing"), ("page", "1")]);
+        let res: AiringResponse = self.fetch_json(url.as_str(), "airing", &["data"]).await?;
+        let mut seen = std::collections::HashSet::new();
+        Ok(res.data.into_iter()
+            .filter(|r| seen.insert(r.anime_session.clone()))
+            .map(|r| Anime {
+                id: r.anime_id,
+                title: r.anime_title,
+                session: r.anime_session,
+                episodes: None,
+                score: None,
+                status: "Currently Airing".to_string(),
+                year: None,
+                anime_type: None,
+            })
+            .collect())
     }
 
     pub async fn get_episodes(&self, session: &str, page: u32) -> Result<SeriesResponse> {
-        let url = format!("{}/?method=series&session={}&page={}", self.base_url, urlencoding::encode(session), page);
-        let resp = self.client.get(&url).send().await?;
-        resp.json::<SeriesResponse>().await.context("Failed to parse episodes response")
+        if let Some(res) = self.episode_cache.as_ref().and_then(|c| c.load(session, page)) {
+            return Ok(res);
+        }
+        let url = self.endpoint(&[("method", "series"), ("session", session), ("page", &page.to_string())]);
+        let res: SeriesResponse = self.fetch_json(url.as_str(), "episodes", &["title", "episodes", "total_pages", "page", "next"]).await?;
+        if let Some(cache) = &self.episode_cache {
+            cache.store(session, page, &res);
+        }
+        Ok(res)
+    }
+
+    /// Synopsis, genres and studio for an anime. Not every backend has this endpoint.
+    pub async fn get_info(&self, session: &str) -> Result<AnimeInfo> {
+        let url = self.endpoint(&[("method", "info"), ("session", session)]);
+        self.fetch_json(url.as_str(), "info", &[]).await
     }
 
     pub async fn get_stream(&self, series_session: &str, episode_session: &str) -> Result<Vec<StreamItem>> {
-        let url = format!("{}/?method=episode&session={}&ep={}", self.base_url, urlencoding::encode(series_session), urlencoding::encode(episode_session));
-        let resp = self.client.get(&url).send().await?;
-        resp.json::<Vec<StreamItem>>().await.context("Failed to parse stream response")
+        let url = self.endpoint(&[("method", "episode"), ("session", series_session), ("ep", episode_session)]);
+        self.fetch_json(url.as_str(), "stream", &["link", "name"]).await
+    }
+
+    /// Backend URL with `params` appended to any query the base URL already carries.
+    fn endpoint(&self, params: &[(&str, &str)]) -> Url {
+        let mut url = self.base_url.clone();
+        url.query_pairs_mut().extend_pairs(params);
+        url
+    }
+
+    /// Fetches `url` and checks the response carries the `required` keys before strict parsing,
+    /// so a backend format change is reported as such rather than as an opaque serde error.
+    async fn fetch_json<T: DeserializeOwned>(&self, url: &str, what: &str, required: &[&str]) -> Result<T> {
+        let (status, body) = self.get_with_retry(url).await?;
+        if let Some(problem) = upstream_problem(status, &body) {
+            return Err(problem);
+        }
+        let parse_error = |e: serde_json::Error| ApiError::Parse(format!("Failed to parse {} response: {}", what, e));
+        let value = serde_json::from_str::<Value>(&body).map_err(parse_error)?;
+        check_schema(&value, what, required)?;
+        serde_json::from_value(value).map_err(parse_error)
+    }
+
+    /// GETs `url`, retrying connection failures and 5xx responses with exponential backoff.
+    /// Any other response, 4xx included, is returned as-is for the caller to judge.
+    async fn get_with_retry(&self, url: &str) -> Result<(StatusCode, String)> {
+        let attempts = RETRY_BACKOFF_MS.len() + 1;
+        let mut backoff = RETRY_BACKOFF_MS.iter();
+        loop {
+            let error = match self.get(url).await.send().await {
+                Ok(resp) if resp.status().is_server_error() => {
+                    let status = resp.status();
+                    let body = resp.text().await.unwrap_or_default();
+                    // Retrying straight away only meets the same challenge.
+                    match upstream_problem(status, &body) {
+                        Some(ApiError::Blocked) => return Err(ApiError::Blocked),
+                        Some(problem) => problem,
+                        None => ApiError::Upstream { status: status.as_u16(), message: format!("Upstream returned {}", status) },
+                    }
+                }
+                Ok(resp) => {
+                    let status = resp.status();
+                    let body = resp.text().await.map_err(|e| self.request_error(e))?;
+                    return Ok((status, body));
+                }
+                // A slow response already cost a full timeout, so only connection failures are retried.
+                Err(e) if e.is_connect() || (e.is_request() && !e.is_timeout()) => self.request_error(e),
+                Err(e) => return Err(self.request_error(e)),
+            };
+            match backoff.next() {
+                Some(&ms) => tokio::time::sleep(std::time::Duration::from_millis(ms)).await,
+                None => return Err(error.after_retries(attempts)),
+            }
+        }
     }
 
     pub async fn extract_stream_url(&self, kwik_url: &str) -> Result<String> {
-        let f_page = self.client.get(kwik_url)
-            .header(REFERER, "https://kwik.cx/")
-            .send().await?.text().await?;
-        
-        let slug_re = SLUG_RE.get_or_init(|| Regex::new("/f/([a-zA-Z0-9]+)").unwrap());
-        let _slug = slug_re.captures(kwik_url)
-            .and_then(|c| c.get(1))
-            .map(|m| m.as_str())
-            .context("Could not extract slug from kwik URL")?;
-        
-        let embed_url = self.decode_kwik_f_page(&f_page)?;
-        let embed_page_url = format!("https://kwik.cx{}", embed_url);
-        let e_page = self.client.get(&embed_page_url)
-            .header(REFERER, kwik_url)
-            .send().await?.text().await?;
+        self.extract_stream(kwik_url).await.map(|(url, _)| url)
+    }
+
+    /// Resolves a kwik `/f/` link to a playable URL, along with the name of the embed-page
+    /// decoding strategy that found it. `/e/` embed links skip straight to the embed page.
+    pub async fn extract_stream(&self, kwik_url: &str) -> Result<(String, &'static str)> {
+        let (kind, _slug) = kwik_slug(kwik_url)
+            .ok_or_else(|| ApiError::ExtractionFailed("Could not extract slug from kwik URL".to_string()))?;
+
+        let (embed_page_url, referer) = if kind == "e" {
+            (kwik_url.to_string(), "https://kwik.cx/")
+        } else {
+            let f_page = self.fetch_kwik_page(kwik_url, "https://kwik.cx/").await?;
+            let embed_url = self.decode_kwik_f_page(&f_page)?;
+            (format!("https://kwik.cx{}", embed_url), kwik_url)
+        };
+        let e_page = self.fetch_kwik_page(&embed_page_url, referer).await?;
         
-        let stream_url = self.decode_kwik_embed_page(&e_page)?;
-        Ok(stream_url)
+        self.decode_kwik_embed_page(&e_page)
+    }
+
+    /// GETs a kwik page, reporting a Cloudflare challenge as such rather than letting it
+    /// fail later as a page with no stream in it.
+    async fn fetch_kwik_page(&self, url: &str, referer: &str) -> Result<String> {
+        let resp = self.get(url).await
+            .header(REFERER, referer)
+            .send().await.map_err(|e| self.request_error(e))?;
+        let status = resp.status();
+        let body = resp.text().await.map_err(|e| self.request_error(e))?;
+        if is_cloudflare_challenge(status, &body) {
+            return Err(ApiError::Blocked);
+        }
+        Ok(body)
     }
 
     fn decode_kwik_f_page(&self, html: &str) -> Result<String> {
@@ -123,42 +489,65 @@ impl AnimeClient {
                 return Ok(url_match.get(1).unwrap().as_str().to_string());
             }
             
-            if let Some(m3u8) = self.extract_m3u8(&decoded) {
-                return Ok(m3u8);
+            if let Some(link) = self.extract_stream_link(&decoded) {
+                return Ok(link);
             }
         }
         
-        let kwik_url_re = KWIK_URL_RE.get_or_init(|| Regex::new(r#"https://kwik\.cx/e/[a-zA-Z0-9]+"#).unwrap());
+        let kwik_url_re = KWIK_URL_RE.get_or_init(|| Regex::new(r#"https://kwik\.cx/e/[a-zA-Z0-9_-]+"#).unwrap());
         if let Some(m) = kwik_url_re.find(html) {
             return Ok(m.as_str().replace("https://kwik.cx", ""));
         }
 
-        bail!("Could not find embed URL in kwik /f/ page")
+        Err(ApiError::ExtractionFailed("Could not find embed URL in kwik /f/ page".to_string()))
     }
 
-    fn decode_kwik_embed_page(&self, html: &str) -> Result<String> {
-        if let Some(decoded) = self.unpack_custom_kwik(html)? {
-            if let Some(m3u8) = self.extract_m3u8(&decoded) {
-                return Ok(m3u8);
+    /// Tries each way of digging the stream URL out of the embed page in turn, so a change to
+    /// one obfuscation layer doesn't break playback outright.
+    fn decode_kwik_embed_page(&self, html: &str) -> Result<(String, &'static str)> {
+        type Strategy = fn(&AnimeClient, &str) -> Result<Option<String>>;
+        let strategies: [(&'static str, Strategy); 3] = [
+            ("kwik unpacker", Self::embed_via_kwik_unpacker),
+            ("p.a.c.k.e.r", Self::embed_via_packer),
+            ("raw link", Self::embed_via_raw_link),
+        ];
+
+        let mut attempts = Vec::new();
+        for &(name, strategy) in strategies.iter() {
+            match strategy(self, html) {
+                Ok(Some(link)) => return Ok((link, name)),
+                Ok(None) => attempts.push(format!("{}: no match", name)),
+                Err(e) => attempts.push(format!("{}: {}", name, e)),
             }
         }
+        Err(ApiError::ExtractionFailed(format!("Could not find an m3u8 or video URL in kwik embed page (tried {})", attempts.join("; "))))
+    }
 
+    fn embed_via_kwik_unpacker(&self, html: &str) -> Result<Option<String>> {
+        Ok(self.unpack_custom_kwik(html)?.and_then(|decoded| self.extract_stream_link(&decoded)))
+    }
+
+    fn embed_via_raw_link(&self, html: &str) -> Result<Option<String>> {
+        Ok(self.extract_stream_link(html))
+    }
+
+    fn embed_via_packer(&self, html: &str) -> Result<Option<String>> {
         let packer_re = PACKER_RE.get_or_init(|| Regex::new(r#"(?s)eval\(function\(p,a,c,k,e,d\)\{.*?\}\('(.*?)',(\d+),(\d+),'(.*?)'\.split\('([|\\\\])'\),\d+,\{\}\)\)"#).unwrap());
         
         for caps in packer_re.captures_iter(html) {
             let packed = caps.get(1).unwrap().as_str();
-            let base = caps.get(2).unwrap().as_str().parse::<usize>()?;
+            let base = parse_number::<usize>(caps.get(2).unwrap().as_str(), "packer base")?;
             let keywords_str = caps.get(4).unwrap().as_str();
             let separator = caps.get(5).unwrap().as_str();
             let keywords: Vec<&str> = keywords_str.split(separator).collect();
             
             let decoded = self.unpack_dean_edwards(packed, base, &keywords)?;
             
-            if let Some(m3u8) = self.extract_m3u8(&decoded) {
-                return Ok(m3u8);
+            if let Some(link) = self.extract_stream_link(&decoded) {
+                return Ok(Some(link));
             }
         }
-        bail!("Could not find m3u8 URL in kwik embed page")
+        Ok(None)
     }
 
     fn unpack_custom_kwik(&self, html: &str) -> Result<Option<String>> {
@@ -167,8 +556,8 @@ impl AnimeClient {
         if let Some(caps) = eval_re.captures(html) {
             let encoded_data = caps.name("cipher").unwrap().as_str();
             let charset = caps.name("mu").unwrap().as_str();
-            let offset = caps.name("bu").unwrap().as_str().parse::<i64>()?;
-            let radix = caps.name("fo").unwrap().as_str().parse::<u32>()?;
+            let offset = parse_number::<i64>(caps.name("bu").unwrap().as_str(), "kwik offset")?;
+            let radix = parse_number::<u32>(caps.name("fo").unwrap().as_str(), "kwik radix")?;
 
             let charset_chars: Vec<char> = charset.chars().collect();
             let separator = charset_chars.get(radix as usize).copied().unwrap_or('|');
@@ -198,9 +587,16 @@ impl AnimeClient {
         Ok(None)
     }
 
-    fn extract_m3u8(&self, text: &str) -> Option<String> {
-        let m3u8_re = M3U8_RE.get_or_init(|| Regex::new(r#"https?://[^'"]+\.m3u8"#).unwrap());
-        m3u8_re.find(text).map(|m| m.as_str().to_string())
+    /// Finds the playable URL in decoded kwik script: an HLS playlist if there is one,
+    /// otherwise a direct `.mp4` or `.mkv` file.
+    fn extract_stream_link(&self, text: &str) -> Option<String> {
+        let link_re = STREAM_LINK_RE.get_or_init(|| Regex::new(r#"https?://[^'"]+\.(m3u8|mp4|mkv)"#).unwrap());
+        let links: Vec<(&str, &str)> = link_re.captures_iter(text)
+            .map(|c| (c.get(0).unwrap().as_str(), c.get(1).unwrap().as_str()))
+            .collect();
+        links.iter().find(|(_, ext)| *ext == "m3u8")
+            .or_else(|| links.first())
+            .map(|(url, _)| url.to_string())
     }
 
     fn unpack_dean_edwards(&self, packed: &str, base: usize, keywords: &[&str]) -> Result<String> {
@@ -229,3 +625,283 @@ impl AnimeClient {
         Ok(result.to_string())
     }
 }
+
+/// A number captured from obfuscated script; only out-of-range values can fail.
+fn parse_number<T: std::str::FromStr>(digits: &str, what: &str) -> Result<T>
+where
+    T::Err: std::fmt::Display,
+{
+    digits.parse().map_err(|e| ApiError::ExtractionFailed(format!("Bad {} '{}': {}", what, digits, e)))
+}
+
+/// The proxy URL without any username or password, for error messages.
+fn configured_proxy(backend: &BackendConfig) -> Option<&str> {
+    backend.proxy.as_deref().map(str::trim).filter(|p| !p.is_empty())
+}
+
+/// Routes `builder` through `backend.proxy` when one is set, so the metadata clients reach
+/// the network the same way as [`AnimeClient`].
+pub fn with_proxy(builder: reqwest::ClientBuilder, backend: &BackendConfig) -> Result<reqwest::ClientBuilder> {
+    let Some(url) = configured_proxy(backend) else { return Ok(builder) };
+    let proxy = reqwest::Proxy::all(url).map_err(|e| ApiError::Config(format!("Invalid proxy '{}': {}", url, e)))?;
+    Ok(builder.proxy(proxy))
+}
+
+/// The proxy reqwest takes from the environment for `url`: the scheme's own variable before
+/// `ALL_PROXY`, and none for hosts covered by `NO_PROXY`. `var` looks a variable up.
+fn env_proxy(url: &Url, var: impl Fn(&str) -> Option<String>) -> Option<String> {
+    let first = |names: &[&str]| names.iter().find_map(|name| var(name)).filter(|v| !v.trim().is_empty());
+    let host = url.host_str()?;
+    let bypassed = first(&["NO_PROXY", "no_proxy"]).is_some_and(|list| {
+        list.split(',').map(str::trim).filter(|entry| !entry.is_empty()).any(|entry| {
+            let domain = entry.trim_start_matches('.');
+            entry == "*" || host == domain || host.ends_with(&format!(".{}", domain))
+        })
+    });
+    if bypassed {
+        return None;
+    }
+    let own = if url.scheme() == "https" { ["HTTPS_PROXY", "https_proxy"] } else { ["HTTP_PROXY", "http_proxy"] };
+    first(&own).or_else(|| first(&["ALL_PROXY", "all_proxy"]))
+}
+
+fn redact_proxy(proxy: &str) -> String {
+    match Url::parse(proxy) {
+        Ok(mut url) => {
+            let _ = url.set_username("");
+            let _ = url.set_password(None);
+            url.to_string()
+        }
+        Err(_) => proxy.to_string(),
+    }
+}
+
+/// Splits a kwik link into its page kind (`"f"` or `"e"`) and video slug.
+pub fn kwik_slug(url: &str) -> Option<(&str, &str)> {
+    let slug_re = SLUG_RE.get_or_init(|| Regex::new("/([fe])/([a-zA-Z0-9_-]+)").unwrap());
+    let caps = slug_re.captures(url)?;
+    Some((caps.get(1)?.as_str(), caps.get(2)?.as_str()))
+}
+
+/// Parses a backend base URL, tolerating trailing slashes and pre-existing query params.
+/// The path always ends in a single `/` so endpoints resolve to `<base>/?method=...`.
+pub fn normalize_base_url(raw: &str) -> Result<Url> {
+    let mut url = Url::parse(raw.trim()).map_err(|e| ApiError::Config(format!("Invalid base URL '{}': {}", raw, e)))?;
+    if url.cannot_be_a_base() || !matches!(url.scheme(), "http" | "https") {
+        return Err(ApiError::Config(format!("Invalid base URL '{}': expected an http(s) URL like {}", raw, DEFAULT_BASE_URL)));
+    }
+    let path = format!("{}/", url.path().trim_end_matches('/'));
+    url.set_path(&path);
+    url.set_fragment(None);
+    if url.query() == Some("") {
+        url.set_query(None);
+    }
+    Ok(url)
+}
+
+/// Describes a response that can't be a usable JSON payload: a Cloudflare challenge, an
+/// HTML error page or other non-JSON body (with a short snippet), or a JSON error status.
+fn upstream_problem(status: StatusCode, body: &str) -> Option<ApiError> {
+    if is_cloudflare_challenge(status, body) {
+        return Some(ApiError::Blocked);
+    }
+    let upstream = |message| Some(ApiError::Upstream { status: status.as_u16(), message });
+    let trimmed = body.trim_start();
+    if !trimmed.starts_with('{') && !trimmed.starts_with('[') {
+        let snippet: String = trimmed.split_whitespace().collect::<Vec<_>>().join(" ").chars().take(120).collect();
+        return upstream(if snippet.is_empty() {
+            format!("Upstream returned an empty response (status {})", status.as_u16())
+        } else {
+            format!("Upstream returned non-JSON (status {}): {}", status.as_u16(), snippet)
+        });
+    }
+    if status.is_success() { None } else { upstream(format!("Upstream returned {}", status)) }
+}
+
+/// A Cloudflare challenge or block page: one of its markers in the body, or a 403 that
+/// isn't a JSON error from the backend itself.
+fn is_cloudflare_challenge(status: StatusCode, body: &str) -> bool {
+    let trimmed = body.trim_start();
+    let json = trimmed.starts_with('{') || trimmed.starts_with('[');
+    (status == StatusCode::FORBIDDEN && !json) || CLOUDFLARE_MARKERS.iter().any(|m| body.contains(m))
+}
+
+/// Verifies an object (or the first element of an array) has every key in `required`.
+fn check_schema(value: &Value, what: &str, required: &[&str]) -> Result<()> {
+    let object = match value {
+        Value::Array(items) => match items.first() {
+            Some(first) => first,
+            None => return Ok(()),
+        },
+        other => other,
+    };
+    let Some(map) = object.as_object() else {
+        return Err(ApiError::Parse(format!("Backend schema changed ({} response is not an object) — Enuma may need an update", what)));
+    };
+    let missing: Vec<&str> = required.iter().copied().filter(|k| !map.contains_key(*k)).collect();
+    if !missing.is_empty() {
+        return Err(ApiError::Parse(format!("Backend schema changed (missing `{}` in {} response) — Enuma may need an update", missing.join("`, `"), what)));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Synthetic kwik embed pages, one per obfuscation layer, built to match the live page's
+    // layout around a made-up stream URL. When extraction breaks, run these first: if they
+    // still pass, kwik changed its page; if they fail, the parser regressed.
+    const CUSTOM_PAGE: &str = include_str!("../tests/fixtures/kwik_embed_custom.html");
+    const PACKER_PAGE: &str = include_str!("../tests/fixtures/kwik_embed_packer.html");
+    const FIXTURE_M3U8: &str = "https://eu-102.files.nextcdn.org/stream/02/11/6f1d8c2e5b7a94e3c0a1f8d7b6e5c4a3d2f1e0b9c8a7d6e5f4c3b2a1/uwu.m3u8";
+
+    fn client() -> AnimeClient {
+        AnimeClient::new(&BackendConfig::default()).unwrap()
+    }
+
+    #[test]
+    fn unpacks_custom_kwik_obfuscator() {
+        let decoded = client().unpack_custom_kwik(CUSTOM_PAGE).unwrap().expect("no eval block found");
+        assert!(decoded.starts_with("const source='"), "decoded to {:?}", decoded);
+        assert!(decoded.contains("hls.loadSource(source)"));
+        assert!(client().unpack_custom_kwik(PACKER_PAGE).unwrap().is_none());
+    }
+
+    #[test]
+    fn unpacks_dean_edwards_packer() {
+        let decoded = client().unpack_dean_edwards("0 1=\\'2://3.4/5.6\\';", 62, &["const", "source", "https", "cdn", "example", "uwu", "m3u8"]).unwrap();
+        assert_eq!(decoded, "const source=\\'https://cdn.example/uwu.m3u8\\';");
+        // Empty keywords leave the token as it was.
+        assert_eq!(client().unpack_dean_edwards("0 1", 62, &["a", ""]).unwrap(), "a 1");
+    }
+
+    #[test]
+    fn decodes_embed_page_per_strategy() {
+        let client = client();
+        assert_eq!(client.decode_kwik_embed_page(CUSTOM_PAGE).unwrap(), (FIXTURE_M3U8.to_string(), "kwik unpacker"));
+        assert_eq!(client.decode_kwik_embed_page(PACKER_PAGE).unwrap(), (FIXTURE_M3U8.to_string(), "p.a.c.k.e.r"));
+
+        let raw = format!("<video><source src=\"{}\"></video>", FIXTURE_M3U8);
+        assert_eq!(client.decode_kwik_embed_page(&raw).unwrap(), (FIXTURE_M3U8.to_string(), "raw link"));
+    }
+
+    #[test]
+    fn stream_link_prefers_hls_then_direct_files() {
+        let client = client();
+        let link = |text: &str| client.extract_stream_link(text);
+        assert_eq!(link("a='https://x.test/v.mp4';b='https://x.test/v.m3u8'").as_deref(), Some("https://x.test/v.m3u8"));
+        assert_eq!(link("a='https://x.test/v.mp4'").as_deref(), Some("https://x.test/v.mp4"));
+        assert_eq!(link("src=\"https://x.test/v.mkv\"").as_deref(), Some("https://x.test/v.mkv"));
+        assert_eq!(link("a='https://x.test/v.mkv';b='https://x.test/v.mp4'").as_deref(), Some("https://x.test/v.mkv"));
+        assert_eq!(link("a='https://x.test/poster.jpg'"), None);
+    }
+
+    #[test]
+    fn embed_page_without_stream_lists_every_strategy() {
+        let err = client().decode_kwik_embed_page("<html><body>File not found</body></html>").unwrap_err().to_string();
+        for name in ["kwik unpacker", "p.a.c.k.e.r", "raw link"] {
+            assert!(err.contains(name), "{} missing from {:?}", name, err);
+        }
+    }
+
+    #[test]
+    fn cloudflare_challenge_is_reported() {
+        let page = include_str!("../tests/fixtures/cloudflare_challenge.html");
+        assert!(is_cloudflare_challenge(StatusCode::SERVICE_UNAVAILABLE, page));
+        assert!(is_cloudflare_challenge(StatusCode::OK, page));
+        assert!(matches!(upstream_problem(StatusCode::SERVICE_UNAVAILABLE, page), Some(ApiError::Blocked)));
+        assert_eq!(ApiError::Blocked.to_string(), CLOUDFLARE_BLOCKED);
+        assert!(is_cloudflare_challenge(StatusCode::FORBIDDEN, "<html>Forbidden</html>"));
+
+        assert!(!is_cloudflare_challenge(StatusCode::FORBIDDEN, r#"{"error":"forbidden"}"#));
+        assert!(!is_cloudflare_challenge(StatusCode::OK, PACKER_PAGE));
+        assert!(!is_cloudflare_challenge(StatusCode::OK, CUSTOM_PAGE));
+    }
+
+    #[test]
+    fn kwik_slug_shapes() {
+        assert_eq!(kwik_slug("https://kwik.cx/f/AbC123"), Some(("f", "AbC123")));
+        assert_eq!(kwik_slug("https://kwik.si/e/a_b-C9?ref=x"), Some(("e", "a_b-C9")));
+        assert_eq!(kwik_slug("kwik.cx/f/xyz/"), Some(("f", "xyz")));
+        assert_eq!(kwik_slug("http://kwik.cx/e/Zx_9-Ab#t=30"), Some(("e", "Zx_9-Ab")));
+        assert_eq!(kwik_slug("https://kwik.cx/d/AbC123"), None);
+        assert_eq!(kwik_slug("https://kwik.cx/"), None);
+    }
+
+    #[test]
+    fn endpoint_encodes_query_values() {
+        let url = client().endpoint(&[("method", "search"), ("q", "fate/stay night & more")]);
+        assert_eq!(url.query(), Some("method=search&q=fate%2Fstay+night+%26+more"));
+
+        let backend = BackendConfig { base_url: Some("https://mirror.test/api//?key=1".to_string()), ..Default::default() };
+        let url = AnimeClient::new(&backend).unwrap().endpoint(&[("method", "search"), ("q", "a b")]);
+        assert_eq!(url.as_str(), "https://mirror.test/api/?key=1&method=search&q=a+b");
+    }
+
+    #[test]
+    fn episode_cache_skips_empty_pages() {
+        let dir = std::env::temp_dir().join(format!("enuma-episode-cache-{}", std::process::id()));
+        let cache = EpisodeCache { dir: dir.clone(), ttl: std::time::Duration::from_secs(3600) };
+        let empty: SeriesResponse = serde_json::from_str(r#"{"title":"Upcoming","episodes":[],"total_pages":0,"page":1,"next":false}"#).unwrap();
+        cache.store("upcoming", 1, &empty);
+        assert!(cache.load("upcoming", 1).is_none());
+
+        let aired: SeriesResponse = serde_json::from_str(r#"{"title":"Aired","episodes":[{"episode":"1","session":"ep1","snapshot":""}],"total_pages":1,"page":1,"next":false}"#).unwrap();
+        cache.store("aired", 1, &aired);
+        assert_eq!(cache.load("aired", 1).map(|res| res.episodes.len()), Some(1));
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn throttle_spaces_every_request() {
+        let backend = BackendConfig { requests_per_sec: Some(20.0), ..Default::default() };
+        let client = AnimeClient::new(&backend).unwrap();
+        let started = std::time::Instant::now();
+        for _ in 0..3 {
+            let _ = client.get("https://kwik.cx/e/abc").await;
+        }
+        assert!(started.elapsed() >= std::time::Duration::from_millis(100), "{:?}", started.elapsed());
+    }
+
+    #[tokio::test]
+    async fn network_errors_name_the_cause() {
+        // Nothing listens on port 9 locally, so the connection is refused straight away.
+        let backend = BackendConfig { base_url: Some("http://127.0.0.1:9/api".to_string()), requests_per_sec: Some(0.0), ..Default::default() };
+        let client = AnimeClient::new(&backend).unwrap();
+        let err = client.get("http://127.0.0.1:9/api").await.send().await.unwrap_err();
+        let shown = client.request_error(err).after_retries(3).to_string();
+        assert!(shown.starts_with("Giving up after 3 attempts: Request failed: "), "{}", shown);
+        assert!(shown.contains("127.0.0.1:9"), "{}", shown);
+        assert!(shown.to_lowercase().contains("connect"), "{}", shown);
+    }
+
+    #[test]
+    fn env_proxy_matches_what_reqwest_uses() {
+        let env = |vars: &'static [(&'static str, &'static str)]| {
+            move |name: &str| vars.iter().find(|(k, _)| *k == name).map(|(_, v)| v.to_string())
+        };
+        let https = Url::parse("https://anime.example/api").unwrap();
+        let both = &[("HTTPS_PROXY", "http://corp:3128"), ("ALL_PROXY", "http://fallback:8080")];
+        assert_eq!(env_proxy(&https, env(both)).as_deref(), Some("http://corp:3128"));
+        assert_eq!(env_proxy(&Url::parse("http://anime.example/").unwrap(), env(both)).as_deref(), Some("http://fallback:8080"));
+        assert_eq!(env_proxy(&https, env(&[("all_proxy", "http://fallback:8080")])).as_deref(), Some("http://fallback:8080"));
+
+        let bypassed = &[("HTTPS_PROXY", "http://corp:3128"), ("ALL_PROXY", "http://fallback:8080"), ("NO_PROXY", "localhost, .example")];
+        assert_eq!(env_proxy(&https, env(bypassed)), None);
+        assert_eq!(env_proxy(&https, env(&[("NO_PROXY", "*"), ("HTTPS_PROXY", "http://corp:3128")])), None);
+        assert_eq!(env_proxy(&https, env(&[("NO_PROXY", "other.example"), ("HTTPS_PROXY", "http://corp:3128")])).as_deref(), Some("http://corp:3128"));
+    }
+
+    #[test]
+    fn upstream_problems_keep_the_status() {
+        let problem = upstream_problem(StatusCode::BAD_GATEWAY, "<html>Bad gateway</html>").unwrap();
+        assert!(matches!(problem, ApiError::Upstream { status: 502, .. }), "{:?}", problem);
+        assert!(matches!(upstream_problem(StatusCode::NOT_FOUND, r#"{"error":"nope"}"#), Some(ApiError::Upstream { status: 404, .. })));
+        assert!(upstream_problem(StatusCode::OK, r#"{"data":[]}"#).is_none());
+
+        let err = check_schema(&serde_json::json!({"data": []}), "search", &["data", "last_page"]).unwrap_err();
+        assert!(matches!(err, ApiError::Parse(_)), "{:?}", err);
+        assert!(matches!(client().decode_kwik_embed_page("<html></html>"), Err(ApiError::ExtractionFailed(_))));
+    }
+}
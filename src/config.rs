@@ -0,0 +1,460 @@
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+/// When an episode is written to history.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum HistoryOn {
+    /// As soon as playback starts.
+    #[default]
+    Start,
+    /// Only after mpv exits cleanly following some real viewing.
+    Finish,
+}
+
+/// What happens once the UI has been idle for `idle_timeout_secs`.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum IdleAction {
+    /// Grey out the UI until the next keypress.
+    #[default]
+    Dim,
+    /// Quit Enuma.
+    Exit,
+}
+
+/// Which URL goes into exported `.strm` files.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum StrmSource {
+    /// The extracted m3u8 URL. Plays anywhere, but expires after a while.
+    #[default]
+    Direct,
+    /// The kwik embed page, which never expires but needs a resolver on the media server.
+    Kwik,
+}
+
+/// Overrides for the API backend, for when the default worker is down or moves.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct BackendConfig {
+    pub base_url: Option<String>,
+    pub user_agent: Option<String>,
+    pub origin: Option<String>,
+    pub referer: Option<String>,
+    /// Per-request timeout in seconds (default 15).
+    pub timeout_secs: Option<u64>,
+    /// Proxy for every backend, kwik, Jikan and AniList request, e.g. `http://proxy:3128`. Without it the
+    /// standard `HTTPS_PROXY`/`HTTP_PROXY`/`ALL_PROXY` variables apply, minus `NO_PROXY` hosts.
+    pub proxy: Option<String>,
+    /// Most backend requests started per second (default 4); 0 removes the limit.
+    pub requests_per_sec: Option<f64>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct Config {
+    pub backend: BackendConfig,
+    /// Unix socket path or `host:port` to listen on for JSON control commands.
+    pub control_socket: Option<String>,
+    /// Upper bound on title width in lists; titles otherwise fill the available panel width.
+    pub title_max_width: Option<usize>,
+    /// Shell command run after mpv exits, with `ENUMA_TITLE` and `ENUMA_EPISODE` set.
+    pub post_play_command: Option<String>,
+    /// Render episode snapshots as ASCII art in the episode list (requires ffmpeg).
+    pub ascii_thumbnails: bool,
+    /// Draw episode snapshots as real images on terminals with the Kitty, iTerm2 or Sixel
+    /// graphics protocol; elsewhere this falls back to `ascii_thumbnails` or no preview.
+    pub show_thumbnails: bool,
+    /// Whether an episode enters history when playback starts (default) or only once it finishes.
+    pub history_on: HistoryOn,
+    /// Most history entries kept (default 50); 0 keeps everything.
+    pub history_limit: Option<usize>,
+    /// Fill in synopsis, genres and score from Jikan (MyAnimeList) in the details panel.
+    pub enrich_metadata: bool,
+    /// Show the in-flight request URL and elapsed time on the loading screen.
+    pub debug: bool,
+    /// Seconds without input before `idle_action` kicks in; unset or 0 disables it.
+    pub idle_timeout_secs: Option<u64>,
+    /// Dim the UI (default) or exit once `idle_timeout_secs` passes without input.
+    pub idle_action: IdleAction,
+    /// Named bundle of mpv video options, see `VIDEO_PRESETS`.
+    pub video_preset: Option<String>,
+    /// Passed to mpv as `--video-aspect-override`, e.g. `4:3` or `1.85`.
+    pub video_aspect: Option<String>,
+    /// mpv `--vf` filter chain, e.g. `bwdif` to deinterlace old broadcasts.
+    pub video_filter: Option<String>,
+    /// Passed to mpv verbatim, one argument per entry, e.g. `["--alang=jpn", "--slang=eng"]`.
+    pub mpv_extra_args: Vec<String>,
+    /// More player arguments for single shows, keyed by anime session, e.g. a `--sub-delay`
+    /// for one release. Added after `mpv_extra_args` so they win.
+    pub per_anime_args: HashMap<String, Vec<String>>,
+    /// Program episodes are played with (default `mpv`).
+    pub player_command: Option<String>,
+    /// Player arguments, one per entry, with `{url}`, `{title}`, `{referrer}`, `{start}`,
+    /// `{watch_later}` and `{extra_args}` filled in; see `DEFAULT_PLAYER_ARGS`.
+    pub player_args_template: Option<Vec<String>>,
+    /// Media-server library folder that `.strm` exports are written into.
+    pub strm_dir: Option<String>,
+    pub strm_source: StrmSource,
+    /// Show "Watching <title> - Episode N" on Discord during playback.
+    pub discord_rpc: bool,
+    /// Application id from the Discord developer portal, used for Rich Presence.
+    pub discord_client_id: Option<String>,
+    /// AniList access token; `s` on the library imports the Watching and Planning lists.
+    pub anilist_token: Option<String>,
+    /// Where `d` saves episodes; defaults to an `Enuma` folder in the videos directory.
+    pub download_dir: Option<String>,
+    /// Desktop notification when a download or batch download finishes.
+    pub notifications: bool,
+    /// Stream name fragment (e.g. `1080p`) played without showing the quality menu.
+    pub preferred_quality: Option<String>,
+    /// Stream name fragments tried in order, e.g. `["1080", "720", "360"]`; takes precedence
+    /// over `preferred_quality` when non-empty.
+    pub quality_preference: Vec<String>,
+    /// Hours episode pages are cached on disk (default 6); 0 turns the cache off.
+    pub episode_cache_hours: Option<u64>,
+    /// What the start screen lists: unset for shows airing now, a search query to list its
+    /// results instead, or `""` for just the welcome text.
+    pub landing: Option<String>,
+    /// Milliseconds the search box has to rest before searching as you type; unset or 0
+    /// searches on Enter only.
+    pub search_debounce_ms: Option<u64>,
+    /// Percent of an episode that has to be played for it to count as watched (default 85).
+    pub watched_threshold: Option<f64>,
+}
+
+/// Where the start screen's list comes from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Landing {
+    Airing,
+    Query(String),
+}
+
+const DEFAULT_HISTORY_LIMIT: usize = 50;
+const DEFAULT_EPISODE_CACHE_HOURS: u64 = 6;
+const DEFAULT_WATCHED_THRESHOLD: f64 = 85.0;
+
+/// mpv's arguments, used when `player_args_template` is unset. `{extra_args}` must be a whole
+/// entry and expands to the video settings plus `mpv_extra_args`; entries using `{start}` or
+/// `{duration_script}` are dropped when there is no start position or script.
+pub const DEFAULT_PLAYER_ARGS: &[&str] = &[
+    "--referrer={referrer}",
+    "--title={title}",
+    "--save-position-on-quit",
+    "--watch-later-directory={watch_later}",
+    "--start={start}",
+    "--script={duration_script}",
+    "{extra_args}",
+    "{url}",
+];
+
+/// Values substituted into the player argument template.
+pub struct PlayerVars<'a> {
+    pub url: &'a str,
+    pub title: &'a str,
+    pub referrer: &'a str,
+    pub watch_later: &'a str,
+    pub start: Option<u64>,
+    /// Session of the show being played, for `per_anime_args`.
+    pub anime_session: Option<&'a str>,
+    /// mpv script that records the episode length in `watch_later`.
+    pub duration_script: Option<&'a str>,
+}
+
+/// Presets accepted by `video_preset`.
+pub const VIDEO_PRESETS: &[&str] = &["anime4k", "deinterlace"];
+
+/// Anime4K "Mode A (Fast)" shader chain, expected in mpv's `~~/shaders` directory.
+const ANIME4K_SHADERS: &[&str] = &[
+    "~~/shaders/Anime4K_Clamp_Highlights.glsl",
+    "~~/shaders/Anime4K_Restore_CNN_M.glsl",
+    "~~/shaders/Anime4K_Upscale_CNN_x2_M.glsl",
+    "~~/shaders/Anime4K_AutoDownscalePre_x2.glsl",
+    "~~/shaders/Anime4K_AutoDownscalePre_x4.glsl",
+    "~~/shaders/Anime4K_Upscale_CNN_x2_S.glsl",
+];
+
+/// `dir()`, except in tests, where every platform directory is one scratch folder per run so
+/// no test can read or write the user's config and data, whatever order the tests run in.
+pub fn platform_dir(dir: fn() -> Option<PathBuf>) -> Option<PathBuf> {
+    if cfg!(test) {
+        return Some(std::env::temp_dir().join(format!("enuma-test-{}", std::process::id())));
+    }
+    dir()
+}
+
+pub fn config_path() -> PathBuf {
+    platform_dir(dirs::config_dir)
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("enuma")
+        .join("config.toml")
+}
+
+impl Config {
+    /// `history_limit` resolved: `None` when history is unbounded.
+    pub fn history_cap(&self) -> Option<usize> {
+        match self.history_limit {
+            Some(0) => None,
+            Some(n) => Some(n),
+            None => Some(DEFAULT_HISTORY_LIMIT),
+        }
+    }
+
+    /// `watched_threshold` as a fraction of the episode.
+    pub fn watched_fraction(&self) -> f64 {
+        self.watched_threshold
+            .filter(|p| p.is_finite() && *p > 0.0)
+            .unwrap_or(DEFAULT_WATCHED_THRESHOLD)
+            .min(100.0) / 100.0
+    }
+
+    /// How long cached episode pages stay fresh, or `None` when caching is off.
+    pub fn episode_cache_ttl(&self) -> Option<std::time::Duration> {
+        match self.episode_cache_hours.unwrap_or(DEFAULT_EPISODE_CACHE_HOURS) {
+            0 => None,
+            hours => Some(std::time::Duration::from_secs(hours * 3600)),
+        }
+    }
+
+    pub fn search_debounce(&self) -> Option<std::time::Duration> {
+        self.search_debounce_ms.filter(|&ms| ms > 0).map(std::time::Duration::from_millis)
+    }
+
+    pub fn landing(&self) -> Option<Landing> {
+        match self.landing.as_deref().map(str::trim) {
+            None => Some(Landing::Airing),
+            Some("") => None,
+            Some(query) => Some(Landing::Query(query.to_string())),
+        }
+    }
+
+    /// Quality fragments to try in order: `quality_preference`, else `preferred_quality`.
+    pub fn quality_order(&self) -> Vec<String> {
+        let list: Vec<String> = self.quality_preference.iter()
+            .map(|q| q.trim().to_string())
+            .filter(|q| !q.is_empty())
+            .collect();
+        if !list.is_empty() {
+            return list;
+        }
+        self.preferred_quality.iter().map(|q| q.trim().to_string()).filter(|q| !q.is_empty()).collect()
+    }
+
+    pub fn load() -> Result<Self> {
+        let path = config_path();
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        toml::from_str(&content)
+            .with_context(|| format!("Invalid config file {}", path.display()))
+    }
+
+    /// Sets one top-level key in config.toml, keeping the other keys as they are (comments are
+    /// not kept, since the file is written back from the parsed table). `None` removes the key so the built-in default applies again.
+    pub fn save_key<T: Serialize>(key: &str, value: Option<T>) -> Result<()> {
+        let path = config_path();
+        let mut root: toml::Table = match std::fs::read_to_string(&path) {
+            Ok(content) => toml::from_str(&content)
+                .with_context(|| format!("Invalid config file {}", path.display()))?,
+            Err(_) => toml::Table::new(),
+        };
+        match value {
+            Some(value) => {
+                let value = toml::Value::try_from(value)
+                    .with_context(|| format!("Cannot store {} in {}", key, path.display()))?;
+                root.insert(key.to_string(), value);
+            }
+            None => {
+                root.remove(key);
+            }
+        }
+
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let tmp_path = path.with_extension("toml.tmp");
+        std::fs::write(&tmp_path, toml::to_string_pretty(&root)?)?;
+        std::fs::rename(&tmp_path, &path)
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// `player_command`, or mpv when unset.
+    pub fn player(&self) -> &str {
+        self.player_command.as_deref().map(str::trim).filter(|p| !p.is_empty()).unwrap_or("mpv")
+    }
+
+    /// The argument template in effect, checked to pass the stream URL somewhere.
+    pub fn player_template(&self) -> Result<Vec<String>> {
+        let template: Vec<String> = match &self.player_args_template {
+            Some(args) => args.clone(),
+            None => DEFAULT_PLAYER_ARGS.iter().map(|a| a.to_string()).collect(),
+        };
+        if !template.iter().any(|a| a.contains("{url}")) {
+            bail!("player_args_template must contain {{url}}");
+        }
+        Ok(template)
+    }
+
+    /// Arguments for `player()` with the template's placeholders filled in from `vars`.
+    pub fn player_args(&self, vars: &PlayerVars) -> Result<Vec<String>> {
+        let extra = self.mpv_args(vars.anime_session)?;
+        let start = vars.start.map(|s| s.to_string());
+        let mut args = Vec::new();
+        for entry in self.player_template()? {
+            if entry == "{extra_args}" {
+                args.extend(extra.iter().cloned());
+                continue;
+            }
+            if (entry.contains("{start}") && start.is_none())
+                || (entry.contains("{duration_script}") && vars.duration_script.is_none()) {
+                continue;
+            }
+            args.push(fill_placeholders(&entry, |name| match name {
+                "url" => Some(vars.url),
+                "title" => Some(vars.title),
+                "referrer" => Some(vars.referrer),
+                "watch_later" => Some(vars.watch_later),
+                "start" => start.as_deref(),
+                "duration_script" => vars.duration_script,
+                _ => None,
+            }));
+        }
+        Ok(args)
+    }
+
+    /// Everything the default mpv template adds between the title and the URL: video
+    /// settings first, then `mpv_extra_args`, then the show's `per_anime_args`, each able to
+    /// override what came before.
+    pub fn mpv_args(&self, anime_session: Option<&str>) -> Result<Vec<String>> {
+        let mut args = self.video_args()?;
+        let per_anime = anime_session.and_then(|s| self.per_anime_args.get(s));
+        for (arg, key) in self.mpv_extra_args.iter().map(|a| (a, "mpv_extra_args"))
+            .chain(per_anime.into_iter().flatten().map(|a| (a, "per_anime_args")))
+        {
+            if arg.trim().is_empty() {
+                bail!("{} must not contain empty entries", key);
+            }
+            args.push(arg.clone());
+        }
+        Ok(args)
+    }
+
+    /// Extra mpv arguments for the configured video preset, aspect override and filter chain.
+    fn video_args(&self) -> Result<Vec<String>> {
+        let mut args = Vec::new();
+
+        if let Some(preset) = self.video_preset.as_deref().map(str::trim).filter(|p| !p.is_empty()) {
+            match preset.to_ascii_lowercase().as_str() {
+                "anime4k" => {
+                    // mpv separates path lists with ';' on Windows and ':' elsewhere.
+                    let sep = if cfg!(windows) { ";" } else { ":" };
+                    args.push(format!("--glsl-shaders={}", ANIME4K_SHADERS.join(sep)));
+                }
+                "deinterlace" => args.push("--deinterlace=yes".to_string()),
+                _ => bail!("unknown video_preset '{}' (expected one of: {})", preset, VIDEO_PRESETS.join(", ")),
+            }
+        }
+
+        if let Some(aspect) = self.video_aspect.as_deref().map(str::trim).filter(|a| !a.is_empty()) {
+            if !is_valid_aspect(aspect) {
+                bail!("invalid video_aspect '{}' (use a ratio like 4:3 or a number like 1.85)", aspect);
+            }
+            args.push(format!("--video-aspect-override={}", aspect));
+        }
+
+        if let Some(filter) = self.video_filter.as_deref().map(str::trim).filter(|f| !f.is_empty()) {
+            validate_filter(filter)?;
+            args.push(format!("--vf={}", filter));
+        }
+
+        Ok(args)
+    }
+}
+
+/// Replaces each `{name}` that `value` knows in one pass, so substituted text (a title
+/// containing `{url}`, say) is never expanded again. Unknown placeholders are left as written.
+fn fill_placeholders<'a>(template: &str, value: impl Fn(&str) -> Option<&'a str>) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        out.push_str(&rest[..open]);
+        let after = &rest[open + 1..];
+        match after.find('}').and_then(|close| value(&after[..close]).map(|v| (close, v))) {
+            Some((close, v)) => {
+                out.push_str(v);
+                rest = &after[close + 1..];
+            }
+            None => {
+                out.push('{');
+                rest = after;
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+fn is_valid_aspect(aspect: &str) -> bool {
+    let positive = |s: &str| s.parse::<f64>().is_ok_and(|n| n.is_finite() && n > 0.0);
+    match aspect.split_once(':') {
+        Some((w, h)) => positive(w) && positive(h),
+        None => aspect == "no" || positive(aspect),
+    }
+}
+
+/// Rejects filter strings that could be mistaken for another mpv option or split the argument.
+fn validate_filter(filter: &str) -> Result<()> {
+    if filter.starts_with('-') {
+        bail!("video_filter must be a filter chain, not an mpv option");
+    }
+    if filter.chars().any(char::is_control) {
+        bail!("video_filter must not contain control characters");
+    }
+    let mut depth = 0i32;
+    for c in filter.chars() {
+        match c {
+            '[' => depth += 1,
+            ']' if depth == 0 => bail!("video_filter has an unmatched ']'"),
+            ']' => depth -= 1,
+            _ => {}
+        }
+    }
+    if depth != 0 || !filter.matches('"').count().is_multiple_of(2) {
+        bail!("video_filter has unbalanced brackets or quotes");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_the_readme_style_toml() {
+        let config: Config = toml::from_str(r#"
+            control_socket = "/tmp/enuma.sock"
+            history_on = "finish"
+            watched_threshold = 90
+            quality_preference = ["1080", "720"]
+
+            [backend]
+            base_url = "https://my-mirror.example.workers.dev"
+            requests_per_sec = 2
+
+            [per_anime_args]
+            "abc-123" = ["--sub-delay=1.2"]
+        "#).unwrap();
+        assert_eq!(config.control_socket.as_deref(), Some("/tmp/enuma.sock"));
+        assert_eq!(config.history_on, HistoryOn::Finish);
+        assert_eq!(config.watched_fraction(), 0.9);
+        assert_eq!(config.quality_order(), ["1080", "720"]);
+        assert_eq!(config.backend.base_url.as_deref(), Some("https://my-mirror.example.workers.dev"));
+        assert_eq!(config.backend.requests_per_sec, Some(2.0));
+        assert_eq!(config.per_anime_args["abc-123"], ["--sub-delay=1.2"]);
+    }
+}
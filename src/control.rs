@@ -0,0 +1,116 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::sync::{mpsc, oneshot};
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+pub enum ControlCommand {
+    Play { title: String, episode: Option<u32> },
+    Search { query: String },
+    Status,
+    Quit,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ControlResponse {
+    pub ok: bool,
+    pub message: String,
+}
+
+impl ControlResponse {
+    pub fn ok(message: impl Into<String>) -> Self {
+        Self { ok: true, message: message.into() }
+    }
+
+    pub fn err(message: impl Into<String>) -> Self {
+        Self { ok: false, message: message.into() }
+    }
+}
+
+pub struct ControlRequest {
+    pub command: ControlCommand,
+    pub reply: oneshot::Sender<ControlResponse>,
+}
+
+/// Starts listening on `addr` and forwards every parsed command to the returned channel.
+/// `addr` is treated as a TCP address when it parses as one, otherwise as a unix socket path.
+/// Commands are unauthenticated, so TCP addresses must be loopback ones.
+pub async fn spawn(addr: &str) -> Result<mpsc::UnboundedReceiver<ControlRequest>> {
+    let (tx, rx) = mpsc::unbounded_channel();
+
+    if let Ok(sock_addr) = addr.parse::<std::net::SocketAddr>() {
+        if !sock_addr.ip().is_loopback() {
+            anyhow::bail!("Control socket {} is not a loopback address; use 127.0.0.1 or ::1", addr);
+        }
+        let listener = tokio::net::TcpListener::bind(sock_addr).await
+            .with_context(|| format!("Failed to bind control socket on {}", addr))?;
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(serve(stream, tx.clone()));
+            }
+        });
+        return Ok(rx);
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileTypeExt;
+        // Clear a stale socket left by a previous run, but never a regular file at that path.
+        if std::fs::symlink_metadata(addr).map(|m| m.file_type().is_socket()).unwrap_or(false) {
+            let _ = std::fs::remove_file(addr);
+        }
+        let listener = tokio::net::UnixListener::bind(addr)
+            .with_context(|| format!("Failed to bind control socket at {}", addr))?;
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(serve(stream, tx.clone()));
+            }
+        });
+        Ok(rx)
+    }
+
+    #[cfg(not(unix))]
+    {
+        drop(tx);
+        anyhow::bail!("Control socket must be a host:port address on this platform")
+    }
+}
+
+async fn serve<S: AsyncRead + AsyncWrite + Unpin>(stream: S, tx: mpsc::UnboundedSender<ControlRequest>) {
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut lines = BufReader::new(reader).lines();
+
+    while let Ok(Some(line)) = lines.next_line().await {
+        if line.trim().is_empty() { continue; }
+
+        let response = match serde_json::from_str::<ControlCommand>(&line) {
+            Ok(command) => {
+                let (reply, rx) = oneshot::channel();
+                if tx.send(ControlRequest { command, reply }).is_err() {
+                    break;
+                }
+                rx.await.unwrap_or_else(|_| ControlResponse::err("Enuma is shutting down"))
+            }
+            Err(e) => ControlResponse::err(format!("Invalid command: {}", e)),
+        };
+
+        let mut out = serde_json::to_string(&response).unwrap_or_default();
+        out.push('\n');
+        if writer.write_all(out.as_bytes()).await.is_err() {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn tcp_sockets_stay_on_loopback() {
+        assert!(spawn("0.0.0.0:0").await.is_err());
+        assert!(spawn("192.168.1.10:0").await.is_err());
+        assert!(spawn("127.0.0.1:0").await.is_ok());
+    }
+}
@@ -0,0 +1,48 @@
+use anyhow::{Context, Result};
+use discord_rich_presence::activity::{Activity, ActivityType, Timestamps};
+use discord_rich_presence::{DiscordIpc, DiscordIpcClient};
+
+/// Discord Rich Presence over the desktop app's local IPC socket. The client does blocking
+/// socket IO, so every call runs on tokio's blocking pool and hands the client back when done.
+pub struct Presence {
+    client: DiscordIpcClient,
+}
+
+impl Presence {
+    /// Connects to the first Discord IPC socket that answers and performs the handshake.
+    pub async fn connect(client_id: &str) -> Result<Self> {
+        let mut client = DiscordIpcClient::new(client_id);
+        blocking(move || {
+            client.connect().context("Discord is not running")?;
+            Ok(Self { client })
+        }).await
+    }
+
+    pub async fn set_watching(mut self, title: &str, ep: &str) -> Result<Self> {
+        let started = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or_default();
+        let (title, state) = (title.to_string(), format!("Episode {}", ep));
+        blocking(move || {
+            let activity = Activity::new()
+                .activity_type(ActivityType::Watching)
+                .details(title.as_str())
+                .state(state.as_str())
+                .timestamps(Timestamps::new().start(started));
+            self.client.set_activity(activity).context("Discord IPC write failed")?;
+            Ok(self)
+        }).await
+    }
+
+    pub async fn clear(mut self) -> Result<Self> {
+        blocking(move || {
+            self.client.clear_activity().context("Discord IPC write failed")?;
+            Ok(self)
+        }).await
+    }
+}
+
+async fn blocking<T: Send + 'static>(f: impl FnOnce() -> Result<T> + Send + 'static) -> Result<T> {
+    tokio::task::spawn_blocking(f).await.context("Discord IPC task failed")?
+}
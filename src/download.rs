@@ -0,0 +1,91 @@
+use anyhow::{bail, Context, Result};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use tokio::io::AsyncReadExt;
+use tokio::process::Command;
+use tokio::sync::mpsc::UnboundedSender;
+
+pub enum DownloadEvent {
+    /// Seconds of video written so far, and the total when ffmpeg reported one.
+    Progress { path: PathBuf, done: f64, total: Option<f64> },
+    Finished { path: PathBuf, result: Result<(), String> },
+}
+
+/// Remuxes the HLS stream at `url` into `out_path` with ffmpeg, reporting progress on `tx`.
+/// ffmpeg writes to [`part_path`] and the file only takes its real name once complete, so a
+/// crash or cancel never leaves something that looks downloaded. A failed part is removed.
+pub async fn download_stream(url: &str, out_path: &Path, tx: &UnboundedSender<DownloadEvent>) -> Result<()> {
+    let part = part_path(out_path);
+    // The .part extension hides the container from ffmpeg, so name it explicitly.
+    let format = match out_path.extension().and_then(|e| e.to_str()) {
+        Some("mkv") => "matroska",
+        Some(ext) => ext,
+        None => "mp4",
+    };
+    let mut child = Command::new("ffmpeg")
+        .args(["-hide_banner", "-nostdin", "-y"])
+        .args(["-headers", "Referer: https://kwik.cx/\r\n", "-i", url])
+        .args(["-c", "copy", "-bsf:a", "aac_adtstoasc", "-f", format])
+        .arg(&part)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .context("Failed to run ffmpeg. Is it installed?")?;
+
+    // ffmpeg rewrites its progress line with '\r', so split on both line endings ourselves.
+    let mut stderr = child.stderr.take().context("ffmpeg stderr unavailable")?;
+    let mut buf = [0u8; 4096];
+    let mut line = Vec::new();
+    let mut last_error = String::new();
+    let mut total = None;
+    loop {
+        let n = stderr.read(&mut buf).await?;
+        if n == 0 { break; }
+        for &b in &buf[..n] {
+            if b != b'\r' && b != b'\n' {
+                line.push(b);
+                continue;
+            }
+            let text = String::from_utf8_lossy(&line).into_owned();
+            line.clear();
+            if let Some(d) = field_after(&text, "Duration: ").and_then(parse_clock) {
+                total = Some(d);
+            } else if let Some(done) = field_after(&text, "time=").and_then(parse_clock) {
+                let _ = tx.send(DownloadEvent::Progress { path: out_path.to_path_buf(), done, total });
+            } else if !text.trim().is_empty() {
+                last_error = text;
+            }
+        }
+    }
+
+    let status = child.wait().await?;
+    if !status.success() {
+        let _ = std::fs::remove_file(&part);
+        bail!("ffmpeg failed: {}", last_error.trim());
+    }
+    std::fs::rename(&part, out_path)
+        .with_context(|| format!("Failed to move the finished download to {}", out_path.display()))
+}
+
+/// Where a download of `out_path` is written until it completes: `Ep - E05.mp4.part`.
+fn part_path(out_path: &Path) -> PathBuf {
+    let mut part = out_path.as_os_str().to_owned();
+    part.push(".part");
+    PathBuf::from(part)
+}
+
+fn field_after<'a>(line: &'a str, key: &str) -> Option<&'a str> {
+    let start = line.find(key)? + key.len();
+    line[start..].split([' ', ',']).next()
+}
+
+/// Parses ffmpeg's `hh:mm:ss.xx` into seconds.
+fn parse_clock(value: &str) -> Option<f64> {
+    let mut parts = value.splitn(3, ':');
+    let h: f64 = parts.next()?.parse().ok()?;
+    let m: f64 = parts.next()?.parse().ok()?;
+    let s: f64 = parts.next()?.parse().ok()?;
+    Some(h * 3600.0 + m * 60.0 + s)
+}
@@ -0,0 +1,62 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use crate::config::BackendConfig;
+
+const JIKAN_URL: &str = "https://api.jikan.moe/v4/anime";
+
+/// Extra details pulled from MyAnimeList via Jikan to fill gaps in the backend's metadata.
+#[derive(Debug, Clone)]
+pub struct Metadata {
+    pub synopsis: Option<String>,
+    pub genres: Vec<String>,
+    pub score: Option<f64>,
+}
+
+#[derive(Deserialize)]
+struct JikanResponse {
+    data: Vec<JikanAnime>,
+}
+
+#[derive(Deserialize)]
+struct JikanAnime {
+    synopsis: Option<String>,
+    #[serde(default)]
+    genres: Vec<JikanGenre>,
+    score: Option<f64>,
+}
+
+#[derive(Deserialize)]
+struct JikanGenre {
+    name: String,
+}
+
+#[derive(Clone)]
+pub struct JikanClient {
+    client: reqwest::Client,
+}
+
+impl JikanClient {
+    /// Uses the backend's proxy, so lookups work wherever the backend does.
+    pub fn new(backend: &BackendConfig) -> Result<Self> {
+        let builder = reqwest::Client::builder().timeout(std::time::Duration::from_secs(15));
+        let client = crate::api::with_proxy(builder, backend)?
+            .build()
+            .context("Failed to build HTTP client")?;
+        Ok(Self { client })
+    }
+
+    /// Looks up the best title match; `Ok(None)` when Jikan has nothing for it.
+    pub async fn lookup(&self, title: &str) -> Result<Option<Metadata>> {
+        let url = format!("{}?q={}&limit=1", JIKAN_URL, urlencoding::encode(title));
+        let resp = self.client.get(&url)
+            .send().await?
+            .error_for_status()?;
+        let body = resp.json::<JikanResponse>().await.context("Failed to parse Jikan response")?;
+
+        Ok(body.data.into_iter().next().map(|a| Metadata {
+            synopsis: a.synopsis,
+            genres: a.genres.into_iter().map(|g| g.name).collect(),
+            score: a.score,
+        }))
+    }
+}
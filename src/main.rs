@@ -1,25 +1,95 @@
+mod anilist;
 mod api;
+mod config;
+mod control;
+mod discord;
+mod download;
+mod jikan;
+mod mal;
+mod thumbnail;
 
-use anyhow::Result;
-use api::{AnimeClient, Anime, Episode, StreamItem};
+use anyhow::{Context, Result};
+use api::{AnimeClient, Anime, AnimeInfo, Episode, SearchResponse, SeriesResponse, StreamItem};
+use config::{BackendConfig, Config, HistoryOn, IdleAction, Landing, PlayerVars, StrmSource};
+use control::{ControlCommand, ControlRequest, ControlResponse};
+use download::DownloadEvent;
+use jikan::{JikanClient, Metadata};
 use crossterm::{
-    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode},
+    cursor::Show,
+    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyModifiers},
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
+use image::DynamicImage;
+use rand::Rng;
+use regex::Regex;
 use ratatui::{
-    backend::CrosstermBackend,
+    backend::{Backend, CrosstermBackend},
     layout::{Constraint, Direction, Layout, Rect},
     style::{Color, Modifier, Style},
-    widgets::{Block, Borders, List, ListItem, ListState, Paragraph, Wrap},
+    text::{Line, Span},
+    widgets::{Block, Borders, Clear, Gauge, LineGauge, List, ListItem, ListState, Paragraph, Wrap},
     Frame, Terminal,
 };
-use std::collections::HashSet;
+use ratatui_image::{picker::Picker, protocol::StatefulProtocol, StatefulImage};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::future::Future;
 use std::io::{self, Stdout};
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
 use tokio::process::Command;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::task::JoinHandle;
 use serde::{Deserialize, Serialize};
-use chrono;
+use chrono::{Datelike, NaiveDateTime};
+
+type Tui = Terminal<CrosstermBackend<Stdout>>;
+
+/// Raw mode and the alternate screen for as long as it lives. Dropping it, whether on a
+/// normal exit, an early `?` or a panic, hands the shell back in a usable state.
+struct TerminalGuard;
+
+impl TerminalGuard {
+    fn enter() -> Result<Self> {
+        let hook = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            // Restore first so the panic message lands on the normal screen.
+            restore_terminal();
+            hook(info);
+        }));
+        enable_raw_mode()?;
+        let guard = TerminalGuard;
+        execute!(io::stdout(), EnterAlternateScreen, EnableMouseCapture)?;
+        Ok(guard)
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        restore_terminal();
+    }
+}
+
+fn restore_terminal() {
+    let _ = disable_raw_mode();
+    let _ = execute!(io::stdout(), LeaveAlternateScreen, DisableMouseCapture, Show);
+}
+
+static SAVE_LOCK: Mutex<()> = Mutex::new(());
+static FRANCHISE_RE: OnceLock<Regex> = OnceLock::new();
+
+/// Minimum time mpv must stay open for a session to count as actually watched.
+const MIN_WATCHED: std::time::Duration = std::time::Duration::from_secs(60);
+/// How long the selection has to rest before metadata for it (and its neighbours) is fetched.
+const METADATA_DEBOUNCE: Duration = Duration::from_millis(300);
+/// Searches kept in memory for instant re-searching; the least recently used is dropped first.
+const SEARCH_CACHE_CAP: usize = 50;
+/// Past queries kept in searches.json for Up/Down recall in the search box.
+const RECENT_SEARCHES_CAP: usize = 20;
+/// Status messages kept for the 'L' log overlay.
+const STATUS_LOG_CAP: usize = 20;
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct HistoryItem {
@@ -27,9 +97,31 @@ pub struct HistoryItem {
     pub episode_session: String,
     pub last_episode: String,
     pub last_watched: String,
+    /// Where mpv was stopped in `episode_session`, restored on the next play.
+    #[serde(default)]
+    pub position_secs: Option<u64>,
+}
+
+/// How an mpv session ended.
+#[derive(Default)]
+struct PlaybackOutcome {
+    /// mpv actually ran, so `position_secs` reflects this session.
+    played: bool,
+    /// Played far enough to count, see `counts_as_watched`.
+    watched: bool,
+    /// Saved position on quit; `None` once the episode played to the end.
+    position_secs: Option<u64>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct QueueItem {
+    pub anime: Anime,
+    pub episode_session: String,
+    pub episode: String,
 }
 
-#[derive(PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 enum CurrentScreen {
     Search,
     SearchResults,
@@ -37,23 +129,293 @@ enum CurrentScreen {
     Library,
     History,
     QualitySelection,
+    Queue,
+    Settings,
+    Info,
+}
+
+/// Where the UI was left on exit, restored on the next launch.
+#[derive(Serialize, Deserialize)]
+struct PersistedState {
+    screen: CurrentScreen,
+    selected: Option<usize>,
+}
+
+/// A failed network operation and the inputs needed to run it again.
+#[derive(Clone)]
+enum RetryableAction {
+    Search(String, u32),
+    LoadEpisodes(Anime, u32),
+    FetchStreams(Anime, String, String),
+    Extract(usize),
+}
+
+#[derive(PartialEq, Clone, Copy)]
+enum SearchMode {
+    Fuzzy,
+    Exact,
+}
+
+impl SearchMode {
+    fn toggled(self) -> Self {
+        match self {
+            SearchMode::Fuzzy => SearchMode::Exact,
+            SearchMode::Exact => SearchMode::Fuzzy,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            SearchMode::Fuzzy => "fuzzy",
+            SearchMode::Exact => "exact",
+        }
+    }
+}
+
+/// Ordering applied to search results and the library.
+#[derive(PartialEq, Clone, Copy)]
+enum SortMode {
+    Relevance,
+    Score,
+    Year,
+    Title,
+}
+
+impl SortMode {
+    fn next(self) -> Self {
+        match self {
+            SortMode::Relevance => SortMode::Score,
+            SortMode::Score => SortMode::Year,
+            SortMode::Year => SortMode::Title,
+            SortMode::Title => SortMode::Relevance,
+        }
+    }
+
+    /// Shown after the list title, e.g. "(by score) ".
+    fn suffix(self) -> &'static str {
+        match self {
+            SortMode::Relevance => "",
+            SortMode::Score => "(by score) ",
+            SortMode::Year => "(by year) ",
+            SortMode::Title => "(by title) ",
+        }
+    }
+
+    /// Stable-sorts `view` (indices into `items`); missing scores and years go last.
+    fn apply(self, items: &[&Anime], view: &mut [usize]) {
+        fn desc<T: PartialOrd>(a: Option<T>, b: Option<T>) -> std::cmp::Ordering {
+            match (a, b) {
+                (Some(a), Some(b)) => b.partial_cmp(&a).unwrap_or(std::cmp::Ordering::Equal),
+                (Some(_), None) => std::cmp::Ordering::Less,
+                (None, Some(_)) => std::cmp::Ordering::Greater,
+                (None, None) => std::cmp::Ordering::Equal,
+            }
+        }
+        match self {
+            SortMode::Relevance => {}
+            SortMode::Score => view.sort_by(|&a, &b| desc(items[a].score, items[b].score)),
+            SortMode::Year => view.sort_by(|&a, &b| desc(items[a].year, items[b].year)),
+            SortMode::Title => view.sort_by_cached_key(|&i| items[i].title.to_lowercase()),
+        }
+    }
+}
+
+#[derive(PartialEq, Clone, Copy)]
+enum InputPrompt {
+    QueueFrom,
+    DownloadRange,
+    StartAt,
+    AddToLibrary,
+    GoToEpisode,
+    OpenUrl,
+    DownloadDir,
+    ClearHistory,
+    ExportMal,
+}
+
+impl InputPrompt {
+    fn label(self) -> &'static str {
+        match self {
+            InputPrompt::QueueFrom => "Queue from episode",
+            InputPrompt::DownloadRange => "Download episodes (e.g. 5-12 or 5,7,9)",
+            InputPrompt::StartAt => "Start at (e.g. 12:30)",
+            InputPrompt::AddToLibrary => "Add anime URL or session",
+            InputPrompt::GoToEpisode => "Go to episode",
+            InputPrompt::OpenUrl => "Play kwik link or open anime URL",
+            InputPrompt::DownloadDir => "Download folder (empty for default)",
+            InputPrompt::ClearHistory => "Clear all watch history? Type y to confirm",
+            InputPrompt::ExportMal => "Export library as MAL XML to",
+        }
+    }
+}
+
+/// Options editable on the Settings screen.
+#[derive(Clone, Copy)]
+enum Setting {
+    PreferredQuality,
+    HistoryOn,
+    DiscordRpc,
+    DownloadDir,
+    EpisodeCache,
+}
+
+const SETTINGS: &[Setting] = &[
+    Setting::PreferredQuality,
+    Setting::HistoryOn,
+    Setting::DiscordRpc,
+    Setting::DownloadDir,
+    Setting::EpisodeCache,
+];
+
+/// Values Enter cycles `preferred_quality` through; `None` shows the quality menu.
+const QUALITY_CHOICES: &[Option<&str>] = &[None, Some("1080p"), Some("720p"), Some("480p"), Some("360p")];
+
+impl Setting {
+    fn label(self) -> &'static str {
+        match self {
+            Setting::PreferredQuality => "Preferred quality",
+            Setting::HistoryOn => "Record history on",
+            Setting::DiscordRpc => "Discord Rich Presence",
+            Setting::DownloadDir => "Download folder",
+            Setting::EpisodeCache => "Episode cache",
+        }
+    }
+
+    fn value(self, config: &Config) -> String {
+        match self {
+            Setting::PreferredQuality if !config.quality_preference.is_empty() => config.quality_order().join(" > "),
+            Setting::PreferredQuality => config.preferred_quality.clone().unwrap_or_else(|| "ask".to_string()),
+            Setting::HistoryOn => match config.history_on {
+                HistoryOn::Start => "start".to_string(),
+                HistoryOn::Finish => "finish".to_string(),
+            },
+            Setting::DiscordRpc => if config.discord_rpc { "on" } else { "off" }.to_string(),
+            Setting::DownloadDir => config.download_dir.clone().unwrap_or_else(|| "default".to_string()),
+            Setting::EpisodeCache => match config.episode_cache_ttl() {
+                Some(ttl) => format!("{}h (Enter to clear)", ttl.as_secs() / 3600),
+                None => "off".to_string(),
+            },
+        }
+    }
+}
+
+/// An action held back until the user answers y/n.
+enum Confirm {
+    /// Remove the library entry with this session.
+    RemoveFromLibrary(String),
+}
+
+/// Panels drawn over the current screen until dismissed.
+#[derive(Clone, Copy)]
+enum Overlay {
+    /// Keys for the current screen.
+    Help,
+    /// Recent status messages.
+    StatusLog,
+}
+
+impl Overlay {
+    /// The key that opens the overlay, which also closes it.
+    fn key(self) -> char {
+        match self {
+            Overlay::Help => '?',
+            Overlay::StatusLog => 'L',
+        }
+    }
+}
+
+/// Where keystrokes go; exactly one area owns input at a time.
+#[derive(PartialEq, Clone, Copy)]
+enum Focus {
+    /// The current screen's list or panel: arrows navigate, letters are shortcuts.
+    Content,
+    /// The search box: typing edits the query.
+    SearchBox,
+    /// A status-bar prompt: typing edits `input_buffer`.
+    Prompt(InputPrompt),
+    /// The Library/History filter: typing narrows the list via `list_filter`.
+    Filter,
+}
+
+impl Focus {
+    /// Border colour for a widget that owns input when focus is on `owner`.
+    fn accent(self, owner: Focus) -> Color {
+        match (self == owner, owner) {
+            (true, Focus::SearchBox) => Color::Yellow,
+            (true, _) => Color::Cyan,
+            (false, _) => Color::DarkGray,
+        }
+    }
 }
 
 struct App {
     client: AnimeClient,
+    config: Config,
+    control_rx: Option<UnboundedReceiver<ControlRequest>>,
+    /// Set while an external player runs in the foreground, see `watch_interrupts`.
+    player_running: Arc<AtomicBool>,
+    /// Set on SIGINT; the event loop quits when it sees it.
+    interrupted: Arc<AtomicBool>,
     current_screen: CurrentScreen,
     search_query: String,
     
+    settings_list_state: ListState,
+    /// Modal panel drawn over the current screen.
+    overlay: Option<Overlay>,
+    /// Destructive action waiting for a y/n answer.
+    confirm: Option<Confirm>,
+    /// Recent status messages with the time they appeared, newest last.
+    status_log: VecDeque<(String, String)>,
+    /// Search results ticked with Space, as indices into `search_results`.
+    marked: HashSet<usize>,
+
+    // Detail screen: the anime shown, its scroll offset, and `method=info` results by
+    // session (None when the backend had nothing)
+    info_anime: Option<Anime>,
+    info_scroll: u16,
+    info: HashMap<String, Option<AnimeInfo>>,
+
     // Search Results
     search_results: Vec<Anime>,
+    search_page: u32,
+    search_total_pages: u32,
+    /// The start screen's list (see `Config::landing`), empty until it loads.
+    landing: Vec<Anime>,
+    landing_list_state: ListState,
+    landing_rx: Option<UnboundedReceiver<Result<Vec<Anime>, String>>>,
     search_list_state: ListState,
+    sort_mode: SortMode,
+    search_cache: HashMap<String, SearchResponse>,
+    /// When the pending search-as-you-type fires, pushed back by every edit.
+    search_due: Option<Instant>,
+    /// The search-as-you-type request in flight, aborted once the query changes.
+    search_task: Option<JoinHandle<()>>,
+    search_tx: UnboundedSender<(String, Result<SearchResponse, String>)>,
+    search_rx: UnboundedReceiver<(String, Result<SearchResponse, String>)>,
+    search_cache_order: VecDeque<String>,
+    // Past queries, newest first; while recalling, the index shown and the query typed before
+    recent_searches: Vec<String>,
+    search_recall: Option<(usize, String)>,
     
     // Episode List
     selected_anime: Option<Anime>,
+    /// Episodes shown: `page_episodes`, minus recaps while `hide_recaps` is on and
+    /// episodes not on disk while `offline_only` is on.
     episode_list: Vec<Episode>,
+    page_episodes: Vec<Episode>,
+    hide_recaps: bool,
+    offline_only: bool,
+    /// Numbers of the episodes on this page with a finished download, refreshed when the page
+    /// changes or a download ends rather than checked on every redraw.
+    offline_episodes: HashSet<String>,
     episode_list_state: ListState,
     ep_page: u32,
     ep_total_pages: u32,
+    // Neighbouring episode pages fetched in the background, keyed by (anime session, page)
+    episode_pages: HashMap<(String, u32), SeriesResponse>,
+    page_pending: HashSet<(String, u32)>,
+    page_tx: UnboundedSender<((String, u32), Option<SeriesResponse>)>,
+    page_rx: UnboundedReceiver<((String, u32), Option<SeriesResponse>)>,
 
     // Library
     library: Vec<Anime>,
@@ -62,22 +424,103 @@ struct App {
     // History
     history: Vec<HistoryItem>,
     history_list_state: ListState,
+    history_grouped: bool,
+    history_relative: bool,
+    // Resume positions of episodes with no history entry (history_on = finish), by episode session
+    positions: HashMap<String, u64>,
+
+    // Fuzzy filter over the Library or History screen; list states index the filtered view
+    list_filter: String,
+
+    // Show raw backend session tokens in lists (debug builds of the config only)
+    show_ids: bool,
+
+    // Watch Queue
+    queue: Vec<QueueItem>,
+    queue_list_state: ListState,
+
+    // Watched episode sessions per anime session
+    watched: HashMap<String, HashSet<String>>,
 
     // Quality Selection
     available_streams: Vec<StreamItem>,
     quality_list_state: ListState,
     temp_play_data: Option<(Anime, String, String)>,
-    previous_screen: Option<CurrentScreen>,
+    /// Stream links whose extraction failed this session, skipped when trying another server.
+    failed_streams: HashSet<String>,
+    start_at: Option<u64>,
+
+    // Screens to return to on Esc, most recent last
+    screen_stack: Vec<CurrentScreen>,
+
+    // Last failed operation, re-run with 'r'
+    last_action: Option<RetryableAction>,
 
     // Status
     status_message: String,
+    // Missing external tools, shown on the start screen for the whole session
+    tool_warning: Option<String>,
+    tool_rx: Option<UnboundedReceiver<Option<String>>>,
+    // Errors too long for the status bar, shown in a popup until the next keypress
+    error_popup: Option<String>,
+    status_width: u16,
 
-    // Search focus state
-    is_searching: bool,
+    // Where keystrokes go, and how searches match
+    focus: Focus,
+    search_mode: SearchMode,
+
+    // Single-line prompt shown in the status bar
+    input_buffer: String,
 
     // Loading & Animation state
     is_loading: bool,
     animation_tick: u32,
+    last_input: Instant,
+
+    // ASCII thumbnails, keyed by snapshot URL (None when conversion failed)
+    thumbnails: HashMap<String, Option<String>>,
+    thumb_pending: HashSet<String>,
+    thumb_size: (u16, u16),
+    thumb_tx: UnboundedSender<(String, Option<String>)>,
+    thumb_rx: UnboundedReceiver<(String, Option<String>)>,
+
+    // Inline snapshot images keyed by snapshot URL, for terminals with a graphics protocol
+    picker: Option<Picker>,
+    images: HashMap<String, Option<StatefulProtocol>>,
+    image_pending: HashSet<String>,
+    image_tx: UnboundedSender<(String, Option<DynamicImage>)>,
+    image_rx: UnboundedReceiver<(String, Option<DynamicImage>)>,
+
+    // Jikan metadata, keyed by anime session (None when the lookup missed)
+    jikan: JikanClient,
+    metadata: HashMap<String, Option<Metadata>>,
+    metadata_tasks: HashMap<String, JoinHandle<()>>,
+    metadata_focus: Option<(String, Instant)>,
+    metadata_tx: UnboundedSender<(String, Option<Metadata>)>,
+    metadata_rx: UnboundedReceiver<(String, Option<Metadata>)>,
+
+    // Discord Rich Presence connection, opened on first playback
+    discord: Option<discord::Presence>,
+    // Kept open because on X11 the copied text disappears when the owning handle is dropped
+    clipboard: Option<arboard::Clipboard>,
+
+    // Running ffmpeg downloads, keyed by output file
+    downloads: HashMap<PathBuf, Transfer>,
+    download_tx: UnboundedSender<DownloadEvent>,
+    download_rx: UnboundedReceiver<DownloadEvent>,
+    // Episodes of the running batch download, fetched one after another
+    batch: Vec<BatchItem>,
+    // How far a multi-step operation behind the loading screen has got, when it can be counted
+    progress: Option<f64>,
+}
+
+/// Vim aliases for list movement: `j`/`k` act as Down/Up.
+fn vim_alias(code: KeyCode) -> KeyCode {
+    match code {
+        KeyCode::Char('j') => KeyCode::Down,
+        KeyCode::Char('k') => KeyCode::Up,
+        other => other,
+    }
 }
 
 fn cycle_selection(state: &mut ListState, len: usize, up: bool) {
@@ -92,312 +535,3108 @@ fn cycle_selection(state: &mut ListState, len: usize, up: bool) {
     state.select(Some(i));
 }
 
+/// Replaces characters that are invalid in file names on common filesystems.
+fn sanitize_filename(name: &str) -> String {
+    let cleaned: String = name.chars()
+        .map(|c| if matches!(c, '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|') || c.is_control() { '_' } else { c })
+        .collect();
+    cleaned.trim().trim_end_matches('.').to_string()
+}
+
+/// `<dir>/<title>/<title> - E<ep>.<ext>`, the layout Kodi and Jellyfin scan.
+fn episode_path(dir: &std::path::Path, title: &str, ep: &str, ext: &str) -> PathBuf {
+    let title = sanitize_filename(title);
+    let ep = match ep.parse::<u32>() {
+        Ok(n) => format!("{:02}", n),
+        Err(_) => sanitize_filename(ep),
+    };
+    dir.join(&title).join(format!("{} - E{}.{}", title, ep, ext))
+}
+
+/// [`episode_path`], creating the folder.
+fn episode_file(dir: &std::path::Path, title: &str, ep: &str, ext: &str) -> Result<PathBuf> {
+    let path = episode_path(dir, title, ep, ext);
+    if let Some(folder) = path.parent() {
+        std::fs::create_dir_all(folder)
+            .with_context(|| format!("Failed to create {}", folder.display()))?;
+    }
+    Ok(path)
+}
+
+/// Writes a `.strm` file holding `url` for the episode.
+fn write_strm(dir: &std::path::Path, title: &str, ep: &str, url: &str) -> Result<PathBuf> {
+    let path = episode_file(dir, title, ep, "strm")?;
+    std::fs::write(&path, format!("{}\n", url))
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    Ok(path)
+}
+
+/// "Library: 42 shows · History: 18 watched · Last: Frieren Ep 5" for the start screen,
+/// or a nudge to get started when both are empty.
+fn dashboard(library: &[Anime], history: &[HistoryItem]) -> String {
+    if library.is_empty() && history.is_empty() {
+        return "Nothing here yet: press '/' to find a show, then 'f' to keep it in your library.".to_string();
+    }
+    let shows = if library.len() == 1 { "show" } else { "shows" };
+    let mut line = format!("Library: {} {} · History: {} watched", library.len(), shows, history.len());
+    if let Some(last) = history.first() {
+        line.push_str(&format!(" · Last: {} Ep {}", last.anime.title, last.last_episode));
+    }
+    line
+}
+
+/// Keeps the first entry for each session. Returns whether anything was removed.
+fn dedup_by_session<T>(items: &mut Vec<T>, session: impl Fn(&T) -> &String) -> bool {
+    let before = items.len();
+    let mut seen = HashSet::new();
+    items.retain(|item| seen.insert(session(item).clone()));
+    items.len() != before
+}
+
+/// The first of `<path>.bak`, `<path>.bak.1`, ... that doesn't exist, so an earlier
+/// backup is never replaced.
+fn backup_path(path: &std::path::Path) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    (0..)
+        .map(|n| path.with_file_name(if n == 0 { format!("{}.bak", name) } else { format!("{}.bak.{}", name, n) }))
+        .find(|candidate| !candidate.exists())
+        .unwrap_or_else(|| path.with_extension("bak"))
+}
+
+/// Where disposable data such as cached episode pages lives.
+fn cache_dir() -> PathBuf {
+    config::platform_dir(dirs::cache_dir)
+        .unwrap_or_else(std::env::temp_dir)
+        .join("enuma")
+}
+
+/// Where library, history and other user data lives. This sticks with `dirs` rather than
+/// `directories::ProjectDirs`, which would nest Windows data under `enuma\data` and so
+/// strand files already kept in `%APPDATA%\enuma`.
 fn data_dir() -> PathBuf {
-    let dir = dirs::data_dir()
+    let dir = config::platform_dir(dirs::data_dir)
         .unwrap_or_else(|| PathBuf::from("."))
         .join("enuma");
     std::fs::create_dir_all(&dir).ok();
     dir
 }
 
-fn truncate_str(s: &str, max_chars: usize) -> String {
-    let mut chars = s.chars();
-    let truncated: String = chars.by_ref().take(max_chars).collect();
-    if chars.next().is_some() {
-        format!("{}...", truncated)
+/// Writes `data` to `path` atomically (temp file + rename), one save at a time across all threads.
+fn write_json_atomic<T: Serialize>(path: &std::path::Path, data: &T) -> Result<()> {
+    let _guard = SAVE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let content = serde_json::to_string_pretty(data)?;
+    std::fs::write(&tmp_path, content)?;
+    std::fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+/// Longest run of episodes a single download range may cover.
+const MAX_RANGE_EPISODES: u32 = 2000;
+
+/// Parses "5-12", "5,7,9" or a mix such as "1-3, 8" into sorted, distinct episode numbers.
+fn parse_episode_numbers(input: &str) -> Result<Vec<u32>> {
+    let number = |s: &str| s.trim().parse::<u32>().map_err(|_| anyhow::anyhow!("'{}' is not an episode number", s.trim()));
+    let mut numbers = std::collections::BTreeSet::new();
+    for part in input.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        let (start, end) = match part.split_once('-') {
+            Some((start, end)) => (number(start)?, number(end)?),
+            None => (number(part)?, number(part)?),
+        };
+        if start > end {
+            anyhow::bail!("'{}' runs backwards", part);
+        }
+        if end - start >= MAX_RANGE_EPISODES {
+            anyhow::bail!("'{}' is more than {} episodes", part, MAX_RANGE_EPISODES);
+        }
+        numbers.extend(start..=end);
+    }
+    if numbers.is_empty() {
+        anyhow::bail!("no episodes given");
+    }
+    Ok(numbers.into_iter().collect())
+}
+
+/// Estimates which page holds episode `number` from the first page's size and numbering,
+/// since sequels often continue the numbering of earlier seasons.
+fn page_for_episode(first: &SeriesResponse, number: u32) -> u32 {
+    let per_page = first.episodes.len().max(1) as u32;
+    let offset = first.episodes.first()
+        .and_then(|e| e.episode.parse::<f64>().ok())
+        .map(|n| n as u32)
+        .unwrap_or(1);
+    (number.saturating_sub(offset) / per_page + 1).min(first.total_pages.max(1))
+}
+
+/// Per-episode directory mpv writes its resume file into. Stream URLs change on every
+/// extraction, so mpv's own URL-hash lookup never matches; we read the file back ourselves.
+fn watch_later_dir(ep_session: &str) -> PathBuf {
+    data_dir().join("watch_later").join(sanitize_filename(ep_session))
+}
+
+/// Loaded by mpv through `{duration_script}`: writes the episode length into the
+/// watch-later directory, next to the resume file, as soon as mpv knows it.
+const DURATION_SCRIPT: &str = r#"mp.observe_property("duration", "number", function(_, duration)
+    local dir = mp.get_property("watch-later-directory", "")
+    if not duration or dir == "" then return end
+    local file = io.open(dir .. "/" .. "duration", "w")
+    if file then
+        file:write(string.format("duration=%f\n", duration))
+        file:close()
+    end
+end)
+"#;
+
+/// What mpv left in an episode's watch-later directory when it exited.
+#[derive(Default)]
+struct SavedPlayback {
+    /// Where it was quit; `None` once it played to the end.
+    position_secs: Option<u64>,
+    /// Length of the episode, from `DURATION_SCRIPT`.
+    duration_secs: Option<f64>,
+}
+
+/// Reads and clears what mpv saved in `dir`.
+fn take_saved_playback(dir: &std::path::Path) -> SavedPlayback {
+    let mut saved = SavedPlayback::default();
+    let Ok(entries) = std::fs::read_dir(dir) else { return saved };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let content = std::fs::read_to_string(&path).unwrap_or_default();
+        let value = |key: &str| content.lines()
+            .find_map(|l| l.strip_prefix(key))
+            .and_then(|v| v.trim().parse::<f64>().ok());
+        saved.position_secs = saved.position_secs.or_else(|| value("start=").map(|secs| secs as u64));
+        saved.duration_secs = saved.duration_secs.or_else(|| value("duration=").filter(|d| *d > 0.0));
+        let _ = std::fs::remove_file(&path);
+    }
+    saved
+}
+
+/// Whether a play counts as watched. Knowing the episode's length, that means stopping past
+/// `threshold` of it, or playing to the end; otherwise (another player, or mpv never loaded
+/// the stream) a clean exit after `MIN_WATCHED`.
+fn counts_as_watched(saved: &SavedPlayback, clean_exit: bool, elapsed: Duration, threshold: f64) -> bool {
+    match (saved.duration_secs, saved.position_secs) {
+        (Some(duration), Some(position)) => position as f64 >= duration * threshold,
+        (Some(_), None) => clean_exit,
+        (None, _) => clean_exit && elapsed >= MIN_WATCHED,
+    }
+}
+
+/// Writes `DURATION_SCRIPT` where mpv can load it, or `None` if that fails.
+fn duration_script() -> Option<PathBuf> {
+    let path = data_dir().join("duration.lua");
+    let current = std::fs::read_to_string(&path).is_ok_and(|s| s == DURATION_SCRIPT);
+    (current || std::fs::write(&path, DURATION_SCRIPT).is_ok()).then_some(path)
+}
+
+fn format_timestamp(secs: u64) -> String {
+    if secs >= 3600 {
+        format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
     } else {
-        truncated
+        format!("{}:{:02}", secs / 60, secs % 60)
     }
 }
 
-impl App {
-    fn new() -> Result<Self> {
-        let library = Self::load_data::<Vec<Anime>>("library.json").unwrap_or_default();
-        let history = Self::load_data::<Vec<HistoryItem>>("history.json").unwrap_or_default();
+/// The configured player with its argument template filled in for one episode.
+/// `anime_session` picks the show's `per_anime_args`, if any.
+fn player_command(config: &Config, url: &str, title: &str, anime_session: Option<&str>, ep: &str, start: Option<u64>, watch_later: &std::path::Path) -> Result<Command> {
+    let title = format!("Enuma - {} - Ep {}", title, ep);
+    // The duration script writes into this directory before mpv itself ever does.
+    let script = std::fs::create_dir_all(watch_later).ok()
+        .and_then(|()| duration_script())
+        .map(|p| p.display().to_string());
+    let watch_later = watch_later.display().to_string();
+    let args = config.player_args(&PlayerVars {
+        url,
+        title: &title,
+        referrer: "https://kwik.cx/",
+        watch_later: &watch_later,
+        start,
+        anime_session,
+        duration_script: script.as_deref(),
+    })?;
+    let mut command = Command::new(config.player());
+    command.args(args);
+    Ok(command)
+}
 
-        Ok(Self {
-            client: AnimeClient::new()?,
-            current_screen: CurrentScreen::Search,
-            search_query: String::new(),
-            search_results: Vec::new(),
-            search_list_state: ListState::default(),
-            selected_anime: None,
-            episode_list: Vec::new(),
-            episode_list_state: ListState::default(),
-            ep_page: 1,
-            ep_total_pages: 1,
-            library,
-            library_list_state: ListState::default(),
-            history,
-            history_list_state: ListState::default(),
-            available_streams: Vec::new(),
-            quality_list_state: ListState::default(),
-            temp_play_data: None,
-            previous_screen: None,
-            status_message: String::from("Press '/' to search, 'l' for library, 'h' for history"),
-            is_searching: false,
-            is_loading: false,
-            animation_tick: 0,
+/// Hands `url` to the desktop's default browser. Over SSH or on a bare console there is
+/// nothing to open it in, so that is reported with the link instead.
+fn open_in_browser(url: &str) -> Result<()> {
+    let graphical = cfg!(any(windows, target_os = "macos"))
+        || ["DISPLAY", "WAYLAND_DISPLAY"].iter().any(|v| std::env::var_os(v).is_some_and(|d| !d.is_empty()));
+    if !graphical {
+        anyhow::bail!("No desktop session to open a browser in. The link is {}", url);
+    }
+    open::that_detached(url).with_context(|| format!("Could not open a browser for {}", url))
+}
+
+/// Shows a desktop notification from a throwaway thread, since talking to the notification
+/// daemon can block for a while. Without a daemon (over SSH, minimal window managers) the
+/// error is dropped and nothing is shown.
+fn notify(body: String) {
+    std::thread::spawn(move || {
+        let _ = notify_rust::Notification::new()
+            .appname("Enuma")
+            .summary("Enuma")
+            .body(&body)
+            .show();
+    });
+}
+
+/// A link pasted on the start screen.
+enum PastedUrl {
+    /// A kwik `/f/` player page and its id, played straight away.
+    Kwik { url: String, id: String },
+    /// An animepahe series, opened on its episode list.
+    Anime(String),
+}
+
+fn parse_pasted_url(input: &str) -> Result<PastedUrl> {
+    let input = input.trim();
+    if let Some(k) = input.find("kwik.") {
+        let (_, id) = api::kwik_slug(&input[k..]).context("kwik link has no video id")?;
+        return Ok(PastedUrl::Kwik { url: input.to_string(), id: id.to_string() });
+    }
+    if input.contains("/anime/") {
+        return parse_anime_ref(input).map(PastedUrl::Anime);
+    }
+    anyhow::bail!("unrecognized URL, expected a kwik.cx/f/ or /e/ link or an animepahe /anime/ page")
+}
+
+/// A search for one exact show rather than a title.
+#[derive(Debug, PartialEq)]
+enum DirectRef {
+    Id(u32),
+    Session(String),
+}
+
+/// Recognises `id:12345` and `session:<session or anime URL>` (prefixes in any case);
+/// `None` for an ordinary title search, which includes titles like "ID:INVADED".
+fn parse_direct_ref(query: &str) -> Option<Result<DirectRef>> {
+    let (prefix, value) = query.split_once(':')?;
+    let value = value.trim();
+    match prefix.trim().to_lowercase().as_str() {
+        "id" => value.parse().ok().map(|id| Ok(DirectRef::Id(id))),
+        "session" => Some(parse_anime_ref(value).map(DirectRef::Session)),
+        _ => None,
+    }
+}
+
+/// "Episode 5 — The Duel (2023-04-12)", leaving out whichever of title and date is missing.
+fn episode_label(ep: &Episode) -> String {
+    let mut label = format!("Episode {}", ep.episode);
+    if let Some(title) = ep.title.as_deref().map(str::trim).filter(|t| !t.is_empty()) {
+        label.push_str(&format!(" — {}", title));
+    }
+    if let Some(date) = ep.created_at.as_deref().and_then(|d| d.split_whitespace().next()) {
+        label.push_str(&format!(" ({})", date));
+    }
+    label
+}
+
+/// A placeholder entry for a series known only by session and title.
+fn anime_from_series(session: String, title: String) -> Anime {
+    Anime {
+        id: 0,
+        title,
+        session,
+        episodes: None,
+        score: None,
+        status: "Unknown".to_string(),
+        year: None,
+        anime_type: None,
+    }
+}
+
+/// Extracts an anime session from an animepahe `/anime/<session>` URL or a bare session.
+fn parse_anime_ref(input: &str) -> Result<String> {
+    let input = input.trim();
+    let candidate = match input.find("/anime/") {
+        Some(pos) => input[pos + "/anime/".len()..]
+            .split(['/', '?', '#'])
+            .next()
+            .unwrap_or_default(),
+        None => input,
+    };
+    if candidate.is_empty() {
+        anyhow::bail!("empty reference");
+    }
+    if candidate.chars().all(|c| c.is_ascii_digit()) {
+        anyhow::bail!("numeric ids can't be resolved, paste the anime page URL or its session instead");
+    }
+    if candidate.len() < 8 || !candidate.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+        anyhow::bail!("not an animepahe anime URL or session");
+    }
+    Ok(candidate.to_string())
+}
+
+/// Parses "90", "12:30" or "1:02:03" into seconds.
+fn parse_timestamp(input: &str) -> Option<u64> {
+    let parts: Vec<&str> = input.trim().split(':').collect();
+    if parts.is_empty() || parts.len() > 3 {
+        return None;
+    }
+    let mut secs = 0;
+    for (i, part) in parts.iter().enumerate() {
+        if part.is_empty() || !part.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        let value: u64 = part.parse().ok()?;
+        if i > 0 && value >= 60 {
+            return None;
+        }
+        secs = secs * 60 + value;
+    }
+    Some(secs)
+}
+
+/// Index of the first stream matching the earliest entry of `order` (e.g. "1080p"), where
+/// a stream matches when its name contains the entry, ignoring case.
+fn preferred_stream(streams: &[StreamItem], order: &[String]) -> Option<usize> {
+    order.iter()
+        .map(|q| q.trim().to_lowercase())
+        .filter(|q| !q.is_empty())
+        .find_map(|q| streams.iter().position(|s| s.name.to_lowercase().contains(&q)))
+}
+
+/// How much of a download ffmpeg has written, in seconds of video.
+#[derive(Default)]
+struct Transfer {
+    done: f64,
+    /// Length of the episode, once ffmpeg has printed it.
+    total: Option<f64>,
+}
+
+impl Transfer {
+    fn fraction(&self) -> Option<f64> {
+        self.total.filter(|t| *t > 0.0).map(|t| (self.done / t).clamp(0.0, 1.0))
+    }
+
+    fn label(&self) -> String {
+        match self.fraction() {
+            Some(f) => format!("{:.0}%", f * 100.0),
+            None if self.done > 0.0 => format_timestamp(self.done as u64),
+            None => "starting".to_string(),
+        }
+    }
+}
+
+/// Where an episode in a batch download stands.
+enum BatchStatus {
+    Queued,
+    /// Being extracted or downloaded.
+    Active(Transfer),
+    Done,
+    Failed(String),
+}
+
+struct BatchItem {
+    ep: String,
+    path: PathBuf,
+    status: BatchStatus,
+}
+
+/// Resolves an episode's preferred (or best) stream and downloads it to `path`.
+async fn fetch_episode(
+    client: &AnimeClient,
+    anime_session: &str,
+    ep_session: &str,
+    preferred: &[String],
+    path: &std::path::Path,
+    tx: &UnboundedSender<DownloadEvent>,
+) -> Result<()> {
+    let streams = client.get_stream(anime_session, ep_session).await?;
+    let stream = preferred_stream(&streams, preferred)
+        .and_then(|i| streams.get(i))
+        .or_else(|| best_stream(&streams))
+        .context("No streams found")?;
+    let url = client.extract_stream_url(&stream.link).await?;
+    download::download_stream(&url, path, tx).await
+}
+
+/// Picks the highest-resolution stream, judged by the largest number in its name.
+fn best_stream(streams: &[StreamItem]) -> Option<&StreamItem> {
+    streams.iter().max_by_key(|s| stream_resolution(s))
+}
+
+fn stream_resolution(stream: &StreamItem) -> u32 {
+    stream.name
+        .split(|c: char| !c.is_ascii_digit())
+        .filter_map(|n| n.parse::<u32>().ok())
+        .max()
+        .unwrap_or(0)
+}
+
+/// The next stream after `from`, wrapping around, that hasn't failed yet; with `same_quality`
+/// only mirrors at `from`'s resolution count.
+fn next_untried(streams: &[StreamItem], failed: &HashSet<String>, from: usize, same_quality: bool) -> Option<usize> {
+    let resolution = streams.get(from).map(stream_resolution)?;
+    (1..streams.len())
+        .map(|step| (from + step) % streams.len())
+        .find(|&i| !failed.contains(&streams[i].link) && (!same_quality || stream_resolution(&streams[i]) == resolution))
+}
+
+/// Whether `program` names an existing file, either as a path or somewhere on PATH. Nothing
+/// is run, since the player may be a user's wrapper script with side effects.
+fn tool_available(program: &str) -> bool {
+    let path = std::path::Path::new(program);
+    if path.components().count() > 1 {
+        return path.is_file();
+    }
+    let extensions: Vec<String> = if cfg!(windows) {
+        std::env::var("PATHEXT").unwrap_or_else(|_| ".EXE;.CMD;.BAT".to_string())
+            .split(';').map(str::to_string).chain(std::iter::once(String::new())).collect()
+    } else {
+        vec![String::new()]
+    };
+    std::env::var_os("PATH").is_some_and(|paths| {
+        std::env::split_paths(&paths).any(|dir| {
+            extensions.iter().any(|ext| dir.join(format!("{}{}", program, ext)).is_file())
         })
+    })
+}
+
+/// A warning naming the external programs Enuma needs but can't find on PATH.
+fn missing_tools_warning(player: &str) -> Option<String> {
+    let mut problems = Vec::new();
+    if !tool_available(player) {
+        problems.push(if player == "mpv" {
+            "mpv not found on PATH, so nothing can play (install it from https://mpv.io/installation/)".to_string()
+        } else {
+            format!("player '{}' not found, so nothing can play (check player_command)", player)
+        });
+    }
+    if !tool_available("ffmpeg") {
+        problems.push("ffmpeg not found on PATH, so downloads and ASCII thumbnails won't work".to_string());
     }
+    (!problems.is_empty()).then(|| problems.join("; "))
+}
 
-    fn load_data<T: for<'de> Deserialize<'de>>(filename: &str) -> Result<T> {
-        let path = data_dir().join(filename);
-        if path.exists() {
-            let content = std::fs::read_to_string(path)?;
-            Ok(serde_json::from_str(&content)?)
+fn shell_command(cmd: &str) -> Command {
+    #[cfg(windows)]
+    let mut command = {
+        let mut c = Command::new("cmd");
+        c.arg("/C");
+        c
+    };
+    #[cfg(not(windows))]
+    let mut command = {
+        let mut c = Command::new("sh");
+        c.arg("-c");
+        c
+    };
+    command.arg(cmd);
+    command
+}
+
+/// Runs the user's post-play hook in the background; only spawn failures are reported.
+fn spawn_post_play_hook(cmd: &str, title: &str, ep: &str) -> Result<()> {
+    let mut child = shell_command(cmd)
+        .env("ENUMA_TITLE", title)
+        .env("ENUMA_EPISODE", ep)
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()?;
+    tokio::spawn(async move {
+        let _ = child.wait().await;
+    });
+    Ok(())
+}
+
+/// Index of the lowest-numbered episode in `episodes` past `after` that isn't in `watched`.
+fn first_unwatched(episodes: &[Episode], watched: &HashSet<String>, after: f64) -> Option<usize> {
+    episodes.iter().enumerate()
+        .filter(|(_, ep)| !watched.contains(&ep.session))
+        .filter_map(|(i, ep)| Some((i, ep.episode.trim().parse::<f64>().ok()?)))
+        .filter(|&(_, number)| number > after)
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(i, _)| i)
+}
+
+/// Trims a search query and collapses inner runs of whitespace to single spaces.
+fn tidy_query(s: &str) -> String {
+    s.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn normalize_title(s: &str) -> String {
+    tidy_query(s).to_lowercase()
+}
+
+/// Search cache key for one page of a query; page 1 is just the normalised query.
+fn search_cache_key(query: &str, page: u32) -> String {
+    match page {
+        1 => normalize_title(query),
+        _ => format!("{}\u{0}{}", normalize_title(query), page),
+    }
+}
+
+/// Scores `text` against `pattern` as a case-insensitive subsequence, favouring consecutive
+/// runs and word starts. `None` when the pattern's characters don't all appear in order.
+fn fuzzy_score(pattern: &str, text: &str) -> Option<i64> {
+    let mut pattern = pattern.chars().filter(|c| !c.is_whitespace()).flat_map(char::to_lowercase).peekable();
+    let (mut score, mut run, mut prev) = (0i64, 0i64, ' ');
+    for c in text.chars().flat_map(char::to_lowercase) {
+        let Some(&p) = pattern.peek() else { break };
+        if p == c {
+            run += 1;
+            score += run * 2;
+            if !prev.is_alphanumeric() { score += 3; }
+            pattern.next();
         } else {
-            anyhow::bail!("File not found")
+            run = 0;
         }
+        prev = c;
     }
+    pattern.peek().is_none().then_some(score)
+}
 
-    fn save_data<T: Serialize>(filename: &str, data: &T) -> Result<()> {
-        let path = data_dir().join(filename);
-        let content = serde_json::to_string_pretty(data)?;
-        std::fs::write(path, content)?;
-        Ok(())
+/// Keeps only results whose title contains the whole query as a phrase.
+fn filter_exact(results: Vec<Anime>, query: &str) -> Vec<Anime> {
+    let needle = normalize_title(query);
+    results.into_iter().filter(|a| normalize_title(&a.title).contains(&needle)).collect()
+}
+
+/// Title with season/part/sequel markers removed, so entries of one franchise compare equal.
+/// A title made only of such markers ("86", "Movie") keys on itself rather than on "".
+fn franchise_key(title: &str) -> String {
+    let re = FRANCHISE_RE.get_or_init(|| {
+        Regex::new(r"(?i)\b(season|part|cour|the movie|movie|ova|ona|specials?|final|\d+(st|nd|rd|th)|[ivx]+|\d+)\b").unwrap()
+    });
+    let base = title.split(':').next().unwrap_or(title);
+    let key = normalize_title(&re.replace_all(base, " "))
+        .trim_matches(|c: char| !c.is_alphanumeric())
+        .to_string();
+    if key.is_empty() { normalize_title(title) } else { key }
+}
+
+/// Short " [TV · 2019 · 25 eps]" tag with whatever distinguishing info is known.
+fn entry_tag(anime: &Anime) -> String {
+    let parts: Vec<String> = vec![
+        anime.anime_type.clone(),
+        anime.year.map(|y| y.to_string()),
+        anime.episodes.map(|e| format!("{} eps", e)),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+    if parts.is_empty() { String::new() } else { format!(" [{}]", parts.join(" · ")) }
+}
+
+fn display_width(s: &str) -> usize {
+    Span::raw(s).width()
+}
+
+/// Truncates `s` to at most `max_width` terminal columns, ellipsis included.
+fn truncate_str(s: &str, max_width: usize) -> String {
+    if display_width(s) <= max_width {
+        return s.to_string();
+    }
+    // Too narrow for even the ellipsis: show what fits of it.
+    if max_width < 3 {
+        return ".".repeat(max_width);
+    }
+    let budget = max_width - 3;
+    let mut truncated = String::new();
+    let mut width = 0;
+    for ch in s.chars() {
+        let mut buf = [0u8; 4];
+        let w = display_width(ch.encode_utf8(&mut buf));
+        if width + w > budget { break; }
+        width += w;
+        truncated.push(ch);
+    }
+    format!("{}...", truncated)
+}
+
+fn pad_str(s: &str, width: usize) -> String {
+    let pad = width.saturating_sub(display_width(s));
+    format!("{}{}", s, " ".repeat(pad))
+}
+
+/// Columns left for a title inside a bordered list with a highlight symbol,
+/// after `reserved` columns of markers and suffixes, optionally capped by config.
+fn title_width(area: Rect, reserved: usize, cap: Option<usize>) -> usize {
+    let available = (area.width as usize).saturating_sub(2 + 2 + reserved).max(4);
+    cap.map_or(available, |c| available.min(c))
+}
+
+impl App {
+    fn new() -> Result<Self> {
+        let mut migrated = Vec::new();
+        Self::migrate_cwd_file::<Vec<Anime>>("library.json", &mut migrated);
+        Self::migrate_cwd_file::<Vec<HistoryItem>>("history.json", &mut migrated);
+
+        let mut recovered = Vec::new();
+        let mut library = Self::load_or_recover::<Vec<Anime>>("library.json", &mut recovered);
+        let mut history = Self::load_or_recover::<Vec<HistoryItem>>("history.json", &mut recovered);
+        // Older versions could add the same series twice; drop the copies once and save.
+        if dedup_by_session(&mut library, |a| &a.session) {
+            let _ = Self::save_data("library.json", &library);
+        }
+        if dedup_by_session(&mut history, |h| &h.anime.session) {
+            let _ = Self::save_data("history.json", &history);
+        }
+        let queue = Self::load_or_recover::<Vec<QueueItem>>("queue.json", &mut recovered);
+        let positions = Self::load_or_recover::<HashMap<String, u64>>("positions.json", &mut recovered);
+        let watched = Self::load_or_recover::<HashMap<String, HashSet<String>>>("watched.json", &mut recovered);
+        let state = Self::load_data::<PersistedState>("state.json").ok().flatten();
+        let recent_searches = Self::load_or_recover::<Vec<String>>("searches.json", &mut recovered);
+        let (config, status_message) = match Config::load() {
+            Ok(config) => (config, String::from("Press '/' to search, 'l' for library, 'h' for history")),
+            Err(e) => (Config::default(), format!("Config error, using defaults: {:#}", e)),
+        };
+        let (client, status_message) = match AnimeClient::new(&config.backend) {
+            Ok(client) => (client, status_message),
+            Err(e) => (
+                AnimeClient::new(&BackendConfig::default())?,
+                format!("Backend config error, using the default backend: {:#}", e),
+            ),
+        };
+        // A bad proxy is already reported for the backend client above.
+        let jikan = JikanClient::new(&config.backend).or_else(|_| JikanClient::new(&BackendConfig::default()))?;
+        let client = match config.episode_cache_ttl() {
+            Some(ttl) => client.with_episode_cache(cache_dir().join("episodes"), ttl),
+            None => client,
+        };
+        let status_message = match config.player_template() {
+            Ok(_) if !migrated.is_empty() => format!("Moved {} from this folder to {}", migrated.join(", "), data_dir().display()),
+            Ok(_) => status_message,
+            Err(e) => format!("Config error: {:#}", e),
+        };
+        let status_message = if recovered.is_empty() { status_message } else { recovered.join(". ") };
+        let (thumb_tx, thumb_rx) = mpsc::unbounded_channel();
+        let (image_tx, image_rx) = mpsc::unbounded_channel();
+        let (metadata_tx, metadata_rx) = mpsc::unbounded_channel();
+        let (download_tx, download_rx) = mpsc::unbounded_channel();
+        let (page_tx, page_rx) = mpsc::unbounded_channel();
+        let (search_tx, search_rx) = mpsc::unbounded_channel();
+
+        let mut app = Self {
+            client,
+            config,
+            control_rx: None,
+            player_running: Arc::new(AtomicBool::new(false)),
+            interrupted: Arc::new(AtomicBool::new(false)),
+            current_screen: CurrentScreen::Search,
+            search_query: String::new(),
+            search_results: Vec::new(),
+            search_page: 1,
+            search_total_pages: 1,
+            landing: Vec::new(),
+            landing_list_state: ListState::default(),
+            landing_rx: None,
+            settings_list_state: ListState::default(),
+            overlay: None,
+            confirm: None,
+            status_log: VecDeque::new(),
+            marked: HashSet::new(),
+            info_anime: None,
+            info_scroll: 0,
+            info: HashMap::new(),
+            search_list_state: ListState::default(),
+            sort_mode: SortMode::Relevance,
+            search_cache: HashMap::new(),
+            search_due: None,
+            search_task: None,
+            search_tx,
+            search_rx,
+            search_cache_order: VecDeque::new(),
+            recent_searches,
+            search_recall: None,
+            selected_anime: None,
+            episode_list: Vec::new(),
+            page_episodes: Vec::new(),
+            hide_recaps: false,
+            offline_only: false,
+            offline_episodes: HashSet::new(),
+            episode_list_state: ListState::default(),
+            ep_page: 1,
+            ep_total_pages: 1,
+            episode_pages: HashMap::new(),
+            page_pending: HashSet::new(),
+            page_tx,
+            page_rx,
+            library,
+            library_list_state: ListState::default(),
+            history,
+            history_list_state: ListState::default(),
+            history_grouped: false,
+            history_relative: false,
+            positions,
+            list_filter: String::new(),
+            show_ids: false,
+            queue,
+            queue_list_state: ListState::default(),
+            watched,
+            available_streams: Vec::new(),
+            quality_list_state: ListState::default(),
+            temp_play_data: None,
+            failed_streams: HashSet::new(),
+            start_at: None,
+            screen_stack: Vec::new(),
+            last_action: None,
+            status_message,
+            tool_warning: None,
+            tool_rx: None,
+            error_popup: None,
+            status_width: 80,
+            focus: Focus::Content,
+            search_mode: SearchMode::Fuzzy,
+            input_buffer: String::new(),
+            is_loading: false,
+            animation_tick: 0,
+            last_input: Instant::now(),
+            thumbnails: HashMap::new(),
+            thumb_pending: HashSet::new(),
+            thumb_size: (0, 0),
+            thumb_tx,
+            thumb_rx,
+            picker: None,
+            images: HashMap::new(),
+            image_pending: HashSet::new(),
+            image_tx,
+            image_rx,
+            jikan,
+            metadata: HashMap::new(),
+            metadata_tasks: HashMap::new(),
+            metadata_focus: None,
+            metadata_tx,
+            metadata_rx,
+            discord: None,
+            clipboard: None,
+            downloads: HashMap::new(),
+            batch: Vec::new(),
+            progress: None,
+            download_tx,
+            download_rx,
+        };
+        app.log_status();
+        if let Some(state) = state {
+            app.restore_state(state);
+        }
+        Ok(app)
+    }
+
+    fn persisted_state(&self) -> PersistedState {
+        let selected = match self.current_screen {
+            CurrentScreen::Library => self.library_index(),
+            CurrentScreen::History => self.history_index(),
+            CurrentScreen::Queue => self.queue_list_state.selected(),
+            _ => None,
+        };
+        PersistedState { screen: self.current_screen.clone(), selected }
+    }
+
+    /// Reopens a saved list screen. Screens whose data is fetched per session (search
+    /// results, episodes, qualities) or lists that have since emptied stay on Search.
+    fn restore_state(&mut self, state: PersistedState) {
+        let len = match state.screen {
+            CurrentScreen::Library => self.library.len(),
+            CurrentScreen::History => self.history.len(),
+            CurrentScreen::Queue => self.queue.len(),
+            _ => 0,
+        };
+        if len == 0 { return; }
+        let selected = state.selected.unwrap_or(0).min(len - 1);
+        let list = match state.screen {
+            CurrentScreen::Library => &mut self.library_list_state,
+            CurrentScreen::History => &mut self.history_list_state,
+            _ => &mut self.queue_list_state,
+        };
+        list.select(Some(selected));
+        self.navigate(state.screen);
+    }
+
+    /// Reads `filename` from the data dir; `Ok(None)` when it doesn't exist yet.
+    fn load_data<T: for<'de> Deserialize<'de>>(filename: &str) -> Result<Option<T>> {
+        let path = data_dir().join(filename);
+        let content = match std::fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        serde_json::from_str(&content).map(Some).context("invalid JSON")
+    }
+
+    /// Loads `filename`, or an empty `T` when there is none yet. A file that doesn't parse is
+    /// moved aside to a `.bak` copy and noted in `recovered` before starting fresh, so the
+    /// next save doesn't overwrite what may still be recoverable by hand. One that can't be
+    /// read at all is left where it is, since there is nothing to say it is damaged.
+    fn load_or_recover<T: for<'de> Deserialize<'de> + Default>(filename: &str, recovered: &mut Vec<String>) -> T {
+        let e = match Self::load_data::<T>(filename) {
+            Ok(data) => return data.unwrap_or_default(),
+            Err(e) => e,
+        };
+        if e.downcast_ref::<serde_json::Error>().is_none() {
+            recovered.push(format!("{} could not be read ({:#}); starting without it", filename, e));
+            return T::default();
+        }
+        let path = data_dir().join(filename);
+        let backup = backup_path(&path);
+        recovered.push(match std::fs::rename(&path, &backup) {
+            Ok(()) => format!("{} was unreadable ({:#}); saved it as {} and started fresh", filename, e, backup.display()),
+            Err(err) => format!("{} is unreadable ({:#}) and could not be backed up: {}", filename, e, err),
+        });
+        T::default()
+    }
+
+    /// Moves `filename` from the working directory into `data_dir()` when the data dir has no
+    /// copy yet and the file parses as `T`, so an unrelated file of the same name is left
+    /// alone. Tests never migrate, since the working directory there is the source tree.
+    fn migrate_cwd_file<T: for<'de> Deserialize<'de> + Serialize>(filename: &'static str, migrated: &mut Vec<&'static str>) {
+        let old = PathBuf::from(filename);
+        if cfg!(test) || !old.is_file() || data_dir().join(filename).exists() {
+            return;
+        }
+        let Some(data) = std::fs::read_to_string(&old).ok().and_then(|c| serde_json::from_str::<T>(&c).ok()) else { return };
+        if Self::save_data(filename, &data).is_ok() {
+            let _ = std::fs::remove_file(&old);
+            migrated.push(filename);
+        }
+    }
+
+    /// Writes `data` to `filename` in the data dir; see [`write_json_atomic`].
+    fn save_data<T: Serialize>(filename: &str, data: &T) -> Result<()> {
+        write_json_atomic(&data_dir().join(filename), data)
+    }
+
+    /// Ticks or unticks the highlighted search result and moves on to the next one.
+    fn toggle_marked(&mut self) {
+        let Some(i) = self.search_index() else { return };
+        if !self.marked.remove(&i) {
+            self.marked.insert(i);
+        }
+        let len = self.list_view(CurrentScreen::SearchResults).len();
+        cycle_selection(&mut self.search_list_state, len, false);
+        self.set_status(format!("{} selected. 'f' to add or remove them all, Space to toggle.", self.marked.len()));
+    }
+
+    /// 'f' with ticked search results: adds the ones missing from the library, or removes
+    /// them all when every one is already there.
+    fn toggle_library_marked(&mut self) {
+        let mut picked: Vec<usize> = self.marked.drain().collect();
+        picked.sort_unstable();
+        let picked: Vec<Anime> = picked.into_iter().filter_map(|i| self.search_results.get(i).cloned()).collect();
+        let in_library = |a: &Anime, library: &[Anime]| library.iter().any(|l| l.session == a.session);
+
+        if picked.iter().all(|a| in_library(a, &self.library)) {
+            self.library.retain(|l| !picked.iter().any(|a| a.session == l.session));
+            self.set_status(format!("Removed {} from library", picked.len()));
+        } else {
+            let mut added = 0;
+            for anime in picked {
+                if !in_library(&anime, &self.library) {
+                    self.library.push(anime);
+                    added += 1;
+                }
+            }
+            self.set_status(format!("Added {} to library", added));
+        }
+        let _ = Self::save_data("library.json", &self.library);
+    }
+
+    fn toggle_library(&mut self) {
+        if self.current_screen == CurrentScreen::SearchResults && !self.marked.is_empty() {
+            self.toggle_library_marked();
+            return;
+        }
+        let session = match self.current_screen {
+            CurrentScreen::SearchResults => {
+                self.search_index()
+                    .and_then(|i| self.search_results.get(i))
+                    .map(|a| a.session.as_str())
+            }
+            CurrentScreen::Library => {
+                self.library_index()
+                    .and_then(|i| self.library.get(i))
+                    .map(|a| a.session.as_str())
+            }
+            CurrentScreen::History => {
+                self.history_index()
+                    .and_then(|i| self.history.get(i))
+                    .map(|h| h.anime.session.as_str())
+            }
+            _ => None,
+        };
+
+        let Some(session) = session.map(String::from) else { return };
+
+        if let Some(pos) = self.library.iter().position(|f| f.session == session) {
+            if self.current_screen == CurrentScreen::Library {
+                self.confirm = Some(Confirm::RemoveFromLibrary(session));
+                return;
+            }
+            let title = self.library[pos].title.clone();
+            self.library.remove(pos);
+            self.set_status(format!("Removed '{}' from library", title));
+        } else {
+            let anime = match self.current_screen {
+                CurrentScreen::SearchResults => {
+                    self.search_index()
+                        .and_then(|i| self.search_results.get(i).cloned())
+                }
+                CurrentScreen::History => {
+                    self.history_index()
+                        .and_then(|i| self.history.get(i).map(|h| h.anime.clone()))
+                }
+                _ => None,
+            };
+            if let Some(anime) = anime {
+                self.set_status(format!("Added '{}' to library", anime.title));
+                self.library.push(anime);
+            }
+        }
+        let _ = Self::save_data("library.json", &self.library);
+    }
+
+    /// Handles the key pressed while `confirm` is showing: 'y' goes ahead, anything else cancels.
+    fn answer_confirm(&mut self, confirm: Confirm, yes: bool) {
+        match confirm {
+            Confirm::RemoveFromLibrary(session) => {
+                let Some(pos) = self.library.iter().position(|a| a.session == session) else { return };
+                if !yes {
+                    self.set_status(format!("Kept '{}' in library", self.library[pos].title));
+                    return;
+                }
+                let anime = self.library.remove(pos);
+                self.set_status(format!("Removed '{}' from library", anime.title));
+                let _ = Self::save_data("library.json", &self.library);
+                let len = self.list_view(CurrentScreen::Library).len();
+                if self.library_list_state.selected().is_some_and(|i| i >= len) {
+                    self.library_list_state.select(len.checked_sub(1));
+                }
+            }
+        }
+    }
+
+    fn record_history(&mut self, anime: Anime, ep_session: String, ep_num: String) {
+        let now = chrono::Local::now().format("%Y-%m-%d %H:%M").to_string();
+        let mut position_secs = None;
+
+        if let Some(pos) = self.history.iter().position(|h| h.anime.session == anime.session) {
+            let old = self.history.remove(pos);
+            if old.episode_session == ep_session {
+                position_secs = old.position_secs;
+            }
+        }
+        if let Some(secs) = self.positions.remove(&ep_session) {
+            position_secs = position_secs.or(Some(secs));
+            let _ = Self::save_data("positions.json", &self.positions);
+        }
+
+        self.history.insert(0, HistoryItem {
+            anime,
+            episode_session: ep_session,
+            last_episode: ep_num,
+            last_watched: now,
+            position_secs,
+        });
+        
+        if let Some(cap) = self.config.history_cap() {
+            self.history.truncate(cap);
+        }
+        
+        let _ = Self::save_data("history.json", &self.history);
+    }
+
+    fn is_watched(&self, anime_session: &str, ep_session: &str) -> bool {
+        self.watched.get(anime_session).is_some_and(|eps| eps.contains(ep_session))
+    }
+
+    fn mark_watched(&mut self, anime_session: &str, ep_sessions: impl IntoIterator<Item = String>) {
+        self.watched.entry(anime_session.to_string()).or_default().extend(ep_sessions);
+        let _ = Self::save_data("watched.json", &self.watched);
+    }
+
+    /// Flips the highlighted episode between watched and unwatched.
+    fn toggle_watched(&mut self) {
+        let Some(anime) = self.selected_anime.as_ref() else { return };
+        let Some(i) = self.episode_list_state.selected() else { return };
+        let Some(ep) = self.episode_list.get(i) else { return };
+        let eps = self.watched.entry(anime.session.clone()).or_default();
+        let message = if eps.remove(&ep.session) {
+            format!("Marked Ep {} as unwatched", ep.episode)
+        } else {
+            eps.insert(ep.session.clone());
+            format!("Marked Ep {} as watched", ep.episode)
+        };
+        if eps.is_empty() {
+            self.watched.remove(&anime.session);
+        }
+        self.set_status(message);
+        let _ = Self::save_data("watched.json", &self.watched);
+    }
+
+    /// Marks every episode on the page up to and including the highlighted one as watched.
+    fn mark_watched_up_to_selected(&mut self) {
+        let Some(session) = self.selected_anime.as_ref().map(|a| a.session.clone()) else { return };
+        let Some(i) = self.episode_list_state.selected().filter(|&i| i < self.episode_list.len()) else { return };
+        let eps: Vec<String> = self.episode_list[..=i].iter().map(|ep| ep.session.clone()).collect();
+        self.set_status(format!("Marked episodes up to Ep {} as watched", self.episode_list[i].episode));
+        self.mark_watched(&session, eps);
+    }
+
+    fn saved_position(&self, ep_session: &str) -> Option<u64> {
+        match self.history.iter().find(|h| h.episode_session == ep_session) {
+            Some(item) => item.position_secs,
+            None => self.positions.get(ep_session).copied(),
+        }
+    }
+
+    /// Stores where playback of `ep_session` stopped: on its history entry, or in
+    /// `positions.json` when it has none yet because history is only written on finish.
+    fn set_position(&mut self, ep_session: &str, position_secs: Option<u64>) {
+        if let Some(item) = self.history.iter_mut().find(|h| h.episode_session == ep_session) {
+            if item.position_secs != position_secs {
+                item.position_secs = position_secs;
+                let _ = Self::save_data("history.json", &self.history);
+            }
+            return;
+        }
+        let changed = match position_secs {
+            Some(secs) => self.positions.insert(ep_session.to_string(), secs) != Some(secs),
+            None => self.positions.remove(ep_session).is_some(),
+        };
+        if changed {
+            let _ = Self::save_data("positions.json", &self.positions);
+        }
+    }
+
+    /// Shows `message` in the status bar and records it in the status log.
+    fn set_status(&mut self, message: String) {
+        self.status_message = message;
+        self.log_status();
+    }
+
+    /// Appends the status message to the log unless it is empty or repeats the last entry.
+    fn log_status(&mut self) {
+        let message = self.status_message.trim();
+        if message.is_empty() { return; }
+        if self.status_log.back().is_some_and(|(_, last)| last == message) { return; }
+        if self.status_log.len() == STATUS_LOG_CAP {
+            self.status_log.pop_front();
+        }
+        let entry = (chrono::Local::now().format("%H:%M:%S").to_string(), message.to_string());
+        self.status_log.push_back(entry);
+    }
+
+    /// Awaits `fut` while redrawing the loading screen, so the spinner (and the debug HUD)
+    /// stay live instead of freezing on the last frame.
+    async fn with_loading<T, B: Backend>(&self, terminal: &mut Terminal<B>, fut: impl Future<Output = T>) -> T {
+        tokio::pin!(fut);
+        let started = Instant::now();
+        let mut tick = self.animation_tick;
+        loop {
+            let _ = terminal.draw(|f| draw_loading(f, self, tick, started.elapsed()));
+            tokio::select! {
+                out = &mut fut => return out,
+                _ = tokio::time::sleep(Duration::from_millis(100)) => tick = tick.wrapping_add(1),
+            }
+        }
+    }
+
+    /// [`Self::with_loading`] that gives up on `fut` when Esc is pressed, returning `None`.
+    /// Ctrl+C cancels too and quits. Other keys pressed meanwhile are dropped rather than
+    /// replayed once the work is done.
+    async fn with_cancellable_loading<T>(&self, terminal: &mut Tui, fut: impl Future<Output = T>) -> Option<T> {
+        let cancelled = async {
+            while !self.cancel_requested() {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        };
+        self.with_loading(terminal, async {
+            tokio::select! {
+                out = fut => Some(out),
+                _ = cancelled => None,
+            }
+        }).await
+    }
+
+    /// Drains pending key presses, returning whether one of them was Esc or Ctrl+C.
+    fn cancel_requested(&self) -> bool {
+        while event::poll(Duration::ZERO).unwrap_or(false) {
+            let Ok(Event::Key(key)) = event::read() else { continue };
+            if key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL) {
+                self.interrupted.store(true, Ordering::SeqCst);
+                return true;
+            }
+            if key.code == KeyCode::Esc {
+                return true;
+            }
+        }
+        false
+    }
+
+    async fn perform_search(&mut self, terminal: &mut Tui) {
+        self.focus = Focus::Content;
+        self.search_recall = None;
+        // Stray spaces only change the cache key; the backend URL is encoded by `AnimeClient`.
+        self.search_query = tidy_query(&self.search_query);
+        self.cancel_instant_search();
+        if self.search_query.is_empty() {
+            return;
+        }
+        if let Some(target) = parse_direct_ref(&self.search_query) {
+            if let Err(e) = self.open_direct(terminal, target).await {
+                self.is_loading = false;
+                self.set_status(format!("Could not open '{}': {:#}", self.search_query, e));
+            }
+            return;
+        }
+        self.fetch_search_page(terminal, 1).await;
+    }
+
+    /// Shows one page of results for the query in the search box, from the cache if possible.
+    async fn fetch_search_page(&mut self, terminal: &mut Tui, page: u32) {
+        let key = search_cache_key(&self.search_query, page);
+        let cached = self.cached_search(&key);
+        let from_cache = cached.is_some();
+        let result = match cached {
+            Some(res) => Ok(res),
+            None => {
+                self.is_loading = true;
+                self.set_status(match page {
+                    1 => "Searching...".to_string(),
+                    _ => format!("Searching (Page {})...", page),
+                });
+                let result = self.with_loading(terminal, self.client.search_page(&self.search_query, page)).await;
+                if let Ok(res) = &result {
+                    self.cache_search(key, res.clone());
+                }
+                result
+            }
+        };
+        match result {
+            Ok(res) => {
+                self.is_loading = false;
+                self.last_action = None;
+                self.remember_search();
+                self.show_search_results(res, from_cache);
+            }
+            Err(e) => {
+                self.is_loading = false;
+                self.last_action = Some(RetryableAction::Search(self.search_query.clone(), page));
+                self.show_error(format!("Error: {}. Press 'r' to retry.", e));
+            }
+        }
+    }
+
+    fn show_search_results(&mut self, res: SearchResponse, from_cache: bool) {
+        self.search_page = res.current_page.max(1);
+        self.search_total_pages = res.last_page.max(self.search_page);
+        let fetched = res.data.len();
+        self.search_results = match self.search_mode {
+            SearchMode::Fuzzy => res.data,
+            SearchMode::Exact => filter_exact(res.data, &self.search_query),
+        };
+        self.marked.clear();
+        self.navigate(CurrentScreen::SearchResults);
+        self.search_list_state.select(Some(0));
+        let pages = match self.search_total_pages {
+            1 => String::new(),
+            total => format!(" on page {}/{} (Left/Right for more)", self.search_page, total),
+        };
+        // Exact mode can filter out a whole page while later pages still hold matches.
+        if self.search_results.is_empty() && fetched > 0 && self.search_page < self.search_total_pages {
+            self.set_status(format!("No exact matches among {} results on page {}/{}. Press Right for the next page.",
+                fetched, self.search_page, self.search_total_pages));
+            return;
+        }
+        self.set_status(format!("Found {} results{}{}. 'f' to add to library, 'S' to sort, Enter to view.",
+            self.search_results.len(), pages, if from_cache { " (cached)" } else { "" }));
+    }
+
+    /// Restarts the search-as-you-type wait after an edit, dropping any search for the old text.
+    fn search_edited(&mut self) {
+        let Some(delay) = self.config.search_debounce() else { return };
+        if let Some(task) = self.search_task.take() {
+            task.abort();
+        }
+        self.search_due = Some(Instant::now() + delay);
+    }
+
+    fn cancel_instant_search(&mut self) {
+        self.search_due = None;
+        if let Some(task) = self.search_task.take() {
+            task.abort();
+        }
+    }
+
+    /// Fires the search-as-you-type request once typing has paused, and shows whatever comes
+    /// back as long as it is still for the text in the box.
+    fn poll_instant_search(&mut self) {
+        let current = tidy_query(&self.search_query);
+        while let Ok((query, result)) = self.search_rx.try_recv() {
+            // An aborted task may already have sent its result.
+            if query != current { continue; }
+            self.search_task = None;
+            match result {
+                Ok(res) => {
+                    self.cache_search(search_cache_key(&query, 1), res.clone());
+                    self.show_search_results(res, false);
+                }
+                Err(e) => self.set_status(format!("Search failed: {}", e)),
+            }
+        }
+
+        let Some(due) = self.search_due else { return };
+        if Instant::now() < due { return; }
+        self.search_due = None;
+        if current.chars().count() < 2 || parse_direct_ref(&current).is_some() { return; }
+        if let Some(res) = self.cached_search(&search_cache_key(&current, 1)) {
+            self.show_search_results(res, true);
+            return;
+        }
+        self.set_status(format!("Searching for '{}'...", current));
+        let client = self.client.clone();
+        let tx = self.search_tx.clone();
+        self.search_task = Some(tokio::spawn(async move {
+            let result = client.search(&current).await.map_err(|e| format!("{:#}", e));
+            let _ = tx.send((current, result));
+        }));
+    }
+
+    /// Moves the current query to the front of the recent searches.
+    fn remember_search(&mut self) {
+        let query = self.search_query.trim().to_string();
+        if query.is_empty() { return; }
+        self.recent_searches.retain(|q| !q.eq_ignore_ascii_case(&query));
+        self.recent_searches.insert(0, query);
+        self.recent_searches.truncate(RECENT_SEARCHES_CAP);
+        let _ = Self::save_data("searches.json", &self.recent_searches);
+    }
+
+    /// Steps through recent searches into the search box: `older` goes back in time, and
+    /// stepping forward past the newest restores what was typed.
+    fn recall_search(&mut self, older: bool) {
+        if self.recent_searches.is_empty() { return; }
+        let next = match (self.search_recall.as_ref().map(|(i, _)| *i), older) {
+            (None, true) => Some(0),
+            (None, false) => return,
+            (Some(i), true) => Some((i + 1).min(self.recent_searches.len() - 1)),
+            (Some(0), false) => None,
+            (Some(i), false) => Some(i - 1),
+        };
+        match next {
+            Some(i) => {
+                let draft = match self.search_recall.take() {
+                    Some((_, draft)) => draft,
+                    None => self.search_query.clone(),
+                };
+                self.search_query = self.recent_searches[i].clone();
+                self.search_recall = Some((i, draft));
+            }
+            None => {
+                if let Some((_, draft)) = self.search_recall.take() {
+                    self.search_query = draft;
+                }
+            }
+        }
+    }
+
+    /// A cached search, marked as the most recently used.
+    fn cached_search(&mut self, key: &str) -> Option<SearchResponse> {
+        let res = self.search_cache.get(key).cloned()?;
+        self.search_cache_order.retain(|k| k != key);
+        self.search_cache_order.push_back(key.to_string());
+        Some(res)
+    }
+
+    fn cache_search(&mut self, key: String, res: SearchResponse) {
+        if self.search_cache.insert(key.clone(), res).is_none() {
+            self.search_cache_order.push_back(key);
+        }
+        while self.search_cache_order.len() > SEARCH_CACHE_CAP {
+            if let Some(oldest) = self.search_cache_order.pop_front() {
+                self.search_cache.remove(&oldest);
+            }
+        }
+    }
+
+    async fn load_episodes<B: Backend>(&mut self, terminal: &mut Terminal<B>, page: u32) {
+        if let Some(anime) = &self.selected_anime {
+            let anime = anime.clone();
+            let session = anime.session.clone();
+            self.poll_episode_pages();
+            self.episode_pages.retain(|(s, _), _| *s == session);
+
+            let cached = self.episode_pages.get(&(session.clone(), page)).cloned();
+            let result = match cached {
+                Some(res) => Ok(res),
+                None => {
+                    self.is_loading = true;
+                    self.set_status(format!("Fetching episodes (Page {})...", page));
+                    self.with_loading(terminal, self.client.get_episodes(&session, page)).await
+                }
+            };
+            match result {
+                // Upcoming shows have no episodes yet; stay put rather than open an empty list.
+                Ok(res) if res.episodes.is_empty() => {
+                    self.is_loading = false;
+                    self.last_action = None;
+                    self.set_status(format!("No episodes available yet for '{}'.", anime.title));
+                }
+                Ok(res) => {
+                    self.episode_pages.insert((session.clone(), res.page), res.clone());
+                    self.is_loading = false;
+                    self.last_action = None;
+                    self.page_episodes = res.episodes;
+                    self.filter_episodes();
+                    self.ep_page = res.page;
+                    self.ep_total_pages = res.total_pages;
+                    self.navigate(CurrentScreen::EpisodeList);
+                    self.episode_list_state.select((!self.episode_list.is_empty()).then_some(0));
+                    self.set_status(format!("Page {}/{}. Left/Right for pages. Enter to play.", self.ep_page, self.ep_total_pages));
+                    if self.offline_only {
+                        let shown = self.episode_list.len();
+                        self.set_status(format!("{} Showing {} downloaded episode(s), 'O' for all.", self.status_message, shown));
+                    }
+                    self.prefetch_neighbour_pages(&session);
+                }
+                Err(e) => {
+                    self.is_loading = false;
+                    self.last_action = Some(RetryableAction::LoadEpisodes(anime, page));
+                    self.show_error(format!("Error fetching episodes: {}. Press 'r' to retry.", e));
+                }
+            }
+        }
+    }
+
+    /// Rebuilds `episode_list` from the current page. With `hide_recaps` on, only the first
+    /// episode of each whole number is kept, dropping recaps like "13.5" and duplicates; with
+    /// `offline_only` on, only episodes with a finished download are kept.
+    fn filter_episodes(&mut self) {
+        self.refresh_offline();
+        let mut seen = HashSet::new();
+        let episodes: Vec<Episode> = self.page_episodes.iter()
+            .filter(|ep| {
+                !self.hide_recaps || ep.episode.trim().parse::<f64>()
+                    .is_ok_and(|n| n.fract() == 0.0 && seen.insert(n as i64))
+            })
+            .filter(|ep| !self.offline_only || self.offline_episodes.contains(&ep.episode))
+            .cloned()
+            .collect();
+        self.episode_list = episodes;
+    }
+
+    /// Re-filters the episode list, keeping the highlight on the same episode where it is
+    /// still listed. Returns how many episodes on the page are hidden.
+    fn refilter_episodes(&mut self) -> usize {
+        let selected = self.episode_list_state.selected()
+            .and_then(|i| self.episode_list.get(i))
+            .map(|ep| ep.session.clone());
+        self.filter_episodes();
+        let row = selected.and_then(|s| self.episode_list.iter().position(|ep| ep.session == s));
+        self.episode_list_state.select(row.or_else(|| (!self.episode_list.is_empty()).then_some(0)));
+        self.page_episodes.len() - self.episode_list.len()
+    }
+
+    /// 'H' on the episode list: shows or hides recap and duplicate episodes.
+    fn toggle_recaps(&mut self) {
+        self.hide_recaps = !self.hide_recaps;
+        let hidden = self.refilter_episodes();
+        self.set_status(if self.hide_recaps {
+            format!("Hiding recaps and duplicates ({} on this page)", hidden)
+        } else {
+            "Showing every episode".to_string()
+        });
+    }
+
+    /// 'O' on the episode list: shows only the episodes already downloaded, or every episode.
+    fn toggle_offline(&mut self) {
+        self.offline_only = !self.offline_only;
+        self.refilter_episodes();
+        self.set_status(match (self.offline_only, self.episode_list.len()) {
+            (false, _) => "Showing every episode".to_string(),
+            (true, 0) => format!("No episodes on this page are downloaded to {}", self.download_dir().display()),
+            (true, n) => format!("Showing {} downloaded episode(s) on this page. Enter plays the file.", n),
+        });
+    }
+
+    /// Rechecks which episodes on the current page have a finished download.
+    fn refresh_offline(&mut self) {
+        let offline = self.page_episodes.iter()
+            .filter(|ep| self.local_episode(&ep.episode).is_some())
+            .map(|ep| ep.episode.clone())
+            .collect();
+        self.offline_episodes = offline;
+    }
+
+    /// The downloaded file for an episode of the selected anime, if it's on disk and finished.
+    fn local_episode(&self, ep: &str) -> Option<PathBuf> {
+        let anime = self.selected_anime.as_ref()?;
+        let path = episode_path(&self.download_dir(), &anime.title, ep, "mp4");
+        let in_progress = self.downloads.contains_key(&path)
+            || self.batch.iter().any(|b| b.path == path && matches!(b.status, BatchStatus::Active(_)));
+        (!in_progress && path.is_file()).then_some(path)
+    }
+
+    /// Fetches the pages either side of the current one in the background so paging is instant.
+    fn prefetch_neighbour_pages(&mut self, session: &str) {
+        let (current, total) = (self.ep_page, self.ep_total_pages);
+        for page in [current.saturating_sub(1), current + 1].iter().copied().filter(|&p| p >= 1 && p <= total) {
+            let key = (session.to_string(), page);
+            if self.episode_pages.contains_key(&key) || !self.page_pending.insert(key.clone()) { continue; }
+            let client = self.client.clone();
+            let tx = self.page_tx.clone();
+            tokio::spawn(async move {
+                let res = client.get_episodes(&key.0, key.1).await.ok();
+                let _ = tx.send((key, res));
+            });
+        }
+    }
+
+    /// Moves finished prefetches into the page cache, dropping ones for another anime.
+    fn poll_episode_pages(&mut self) {
+        let current = self.selected_anime.as_ref().map(|a| a.session.clone());
+        while let Ok((key, res)) = self.page_rx.try_recv() {
+            self.page_pending.remove(&key);
+            if let Some(res) = res.filter(|_| current.as_ref() == Some(&key.0)) {
+                self.episode_pages.insert(key, res);
+            }
+        }
+    }
+
+    /// Plays the highlighted episode, from the downloaded file when there is one unless
+    /// `force_menu` asks for the quality menu.
+    async fn play_episode(&mut self, terminal: &mut Tui, force_menu: bool) -> Result<()> {
+        let Some(i) = self.episode_list_state.selected() else { return Ok(()) };
+        let Some(ep) = self.episode_list.get(i) else { return Ok(()) };
+        let ep_session = ep.session.clone();
+        let ep_num = ep.episode.clone();
+        let Some(anime) = self.selected_anime.clone() else { return Ok(()) };
+        match self.local_episode(&ep_num).filter(|_| !force_menu) {
+            Some(path) => self.play_url(terminal, anime, ep_session, ep_num, &path.to_string_lossy()).await,
+            None => self.prepare_stream_selection(terminal, anime, ep_session, ep_num, force_menu).await,
+        }
+    }
+
+    /// Fetches the streams for an episode and opens the quality menu on the preferred one,
+    /// playing it straight away when it exists unless `force_menu` is set.
+    async fn prepare_stream_selection(&mut self, terminal: &mut Tui, anime: Anime, ep_session: String, ep_num: String, force_menu: bool) -> Result<()> {
+        self.is_loading = true;
+        self.set_status(format!("Fetching streams for Ep {}... Esc to cancel", ep_num));
+        let series_session = anime.session.clone();
+        self.selected_anime = Some(anime.clone());
+
+        let Some(result) = self.with_cancellable_loading(terminal, self.client.get_stream(&series_session, &ep_session)).await else {
+            self.is_loading = false;
+            self.set_status(format!("Cancelled fetching streams for Ep {}", ep_num));
+            return Ok(());
+        };
+        match result {
+            Ok(streams) => {
+                self.is_loading = false;
+                self.last_action = None;
+                if streams.is_empty() {
+                    self.set_status("No streams found.".to_string());
+                    return Ok(());
+                }
+                
+                let preferred = preferred_stream(&streams, &self.config.quality_order());
+                self.available_streams = streams;
+                self.quality_list_state.select(Some(preferred.unwrap_or(0)));
+                self.temp_play_data = Some((anime, ep_session, ep_num));
+                self.navigate(CurrentScreen::QualitySelection);
+                self.set_status("Select video quality. Enter to play, 't' to start at a timestamp, 'y' to copy the URL, Esc to go back.".to_string());
+                // Esc during autoplay undoes the Enter that started it, rather than leaving
+                // the user on a menu they never opened.
+                if preferred.is_some() && !force_menu && !self.play_selected_stream(terminal).await? {
+                    self.temp_play_data = None;
+                    self.go_back();
+                }
+            }
+            Err(e) => {
+                 self.is_loading = false;
+                 self.set_status(format!("Error fetching stream: {}. Press 'r' to retry.", e));
+                 self.last_action = Some(RetryableAction::FetchStreams(anime, ep_session, ep_num));
+            }
+        }
+        Ok(())
+    }
+
+    /// Extracts and plays the highlighted stream. Returns `false` when extraction was
+    /// cancelled with Esc, leaving the quality menu as it was.
+    async fn play_selected_stream(&mut self, terminal: &mut Tui) -> Result<bool> {
+        let Some(idx) = self.quality_list_state.selected() else { return Ok(true) };
+        let Some((anime, ep_session, ep_num)) = self.temp_play_data.take() else { return Ok(true) };
+        let Some(link_item) = self.available_streams.get(idx) else {
+            self.temp_play_data = Some((anime, ep_session, ep_num));
+            return Ok(true);
+        };
+
+        let mut link = link_item.link.clone();
+        let mut quality_name = link_item.name.clone();
+        let mut idx = idx;
+
+        // A failed server falls through to the next mirror of the same quality, if there is one.
+        let mut tried = Vec::new();
+        let result = loop {
+            self.is_loading = true;
+            self.set_status(format!("Extracting stream URL ({})... Esc to cancel", quality_name));
+            let Some(extracted) = self.with_cancellable_loading(terminal, self.client.extract_stream(&link)).await else {
+                self.is_loading = false;
+                self.temp_play_data = Some((anime, ep_session, ep_num));
+                self.set_status(format!("Cancelled extracting {}", quality_name));
+                return Ok(false);
+            };
+            let error = match extracted {
+                Ok(found) => break Ok(found),
+                Err(e) => e,
+            };
+            self.failed_streams.insert(link.clone());
+            tried.push(quality_name.clone());
+            match next_untried(&self.available_streams, &self.failed_streams, idx, true) {
+                Some(next) => {
+                    idx = next;
+                    self.quality_list_state.select(Some(next));
+                    link = self.available_streams[next].link.clone();
+                    quality_name = self.available_streams[next].name.clone();
+                }
+                None => break Err(error),
+            }
+        };
+
+        match result {
+            Ok((direct_url, method)) => {
+                self.is_loading = false;
+                self.last_action = None;
+                self.play_url(terminal, anime, ep_session, ep_num, &direct_url).await?;
+                if self.config.debug {
+                    self.set_status(format!("{} (extracted via {})", self.status_message, method));
+                }
+                self.go_back();
+            }
+            Err(e) => {
+                self.is_loading = false;
+                self.temp_play_data = Some((anime, ep_session, ep_num));
+                self.last_action = Some(RetryableAction::Extract(idx));
+                let servers = if tried.len() > 1 { format!(" (tried {})", tried.join(", ")) } else { String::new() };
+                self.show_error(format!("Failed to extract stream{}: {}. Press 'r' to retry or 'n' to try another server.", servers, e));
+            }
+        }
+        Ok(true)
+    }
+
+    /// Plays `url` (a stream or a downloaded file) and records history, watched state and the
+    /// resume position afterwards.
+    async fn play_url(&mut self, terminal: &mut Tui, anime: Anime, ep_session: String, ep_num: String, url: &str) -> Result<()> {
+        let title = anime.title.clone();
+        // Settings that keep the player from starting must not leave a history entry behind.
+        if let Err(e) = self.config.player_template().and(self.config.mpv_args(Some(&anime.session))) {
+            self.set_status(format!("Not playing, check config.toml: {}", e));
+            return Ok(());
+        }
+        let record_on_finish = self.config.history_on == HistoryOn::Finish;
+        if !record_on_finish {
+            self.record_history(anime.clone(), ep_session.clone(), ep_num.clone());
+        }
+        let presence = self.update_presence(Some((&title, &ep_num))).await;
+        let outcome = self.launch_player(terminal, url, &title, Some(&anime.session), &ep_num, &ep_session).await?;
+        let cleared = self.update_presence(None).await;
+        if let Err(e) = presence.and(cleared) {
+            if self.config.debug {
+                self.set_status(format!("{} (Discord: {:#})", self.status_message, e));
+            }
+        }
+        if outcome.watched {
+            self.mark_watched(&anime.session, [ep_session.clone()]);
+        }
+        if record_on_finish && outcome.watched {
+            self.record_history(anime, ep_session.clone(), ep_num);
+        }
+        if outcome.played {
+            self.set_position(&ep_session, outcome.position_secs);
+        }
+        Ok(())
+    }
+
+    /// Moves to the next stream that hasn't failed yet, in any quality, and plays it.
+    async fn try_next_server(&mut self, terminal: &mut Tui) -> Result<()> {
+        let from = self.quality_list_state.selected().unwrap_or(0);
+        match next_untried(&self.available_streams, &self.failed_streams, from, false) {
+            Some(next) => {
+                self.quality_list_state.select(Some(next));
+                self.play_selected_stream(terminal).await.map(|_| ())
+            }
+            None => {
+                self.set_status("Every server has failed for this episode. 'r' retries the last one.".to_string());
+                Ok(())
+            }
+        }
+    }
+
+    /// Writes a `.strm` file for the highlighted quality. Direct URLs are extracted afresh on
+    /// every export, so pressing the key again refreshes an expired entry.
+    async fn export_strm(&mut self, terminal: &mut Tui) {
+        let Some(dir) = self.config.strm_dir.clone().filter(|d| !d.trim().is_empty()) else {
+            self.set_status("Set strm_dir in config.toml to export .strm files".to_string());
+            return;
+        };
+        let Some((anime, _, ep_num)) = self.temp_play_data.clone() else { return };
+        let Some(stream) = self.quality_list_state.selected().and_then(|i| self.available_streams.get(i)) else { return };
+        let (link, quality_name) = (stream.link.clone(), stream.name.clone());
+
+        let url = match self.config.strm_source {
+            StrmSource::Kwik => link,
+            StrmSource::Direct => {
+                self.is_loading = true;
+                self.set_status(format!("Extracting stream URL ({})...", quality_name));
+                let result = self.with_loading(terminal, self.client.extract_stream_url(&link)).await;
+                self.is_loading = false;
+                match result {
+                    Ok(url) => url,
+                    Err(e) => {
+                        self.show_error(format!("Failed to extract stream: {}", e));
+                        return;
+                    }
+                }
+            }
+        };
+
+        self.set_status(match write_strm(std::path::Path::new(&dir), &anime.title, &ep_num, &url) {
+            Ok(path) => format!("Exported {}", path.display()),
+            Err(e) => format!("Export failed: {:#}", e),
+        });
+    }
+
+    /// Opens the highlighted quality's kwik page in the default browser, for when extraction
+    /// or the player fails but the page itself still plays.
+    fn open_stream_in_browser(&mut self) {
+        let Some(stream) = self.quality_list_state.selected().and_then(|i| self.available_streams.get(i)) else { return };
+        self.set_status(match open_in_browser(&stream.link) {
+            Ok(()) => format!("Opened {} in your browser", stream.name),
+            Err(e) => format!("{:#}", e),
+        });
+    }
+
+    /// Resolves the highlighted quality and puts the direct stream URL on the clipboard.
+    async fn copy_stream_url(&mut self, terminal: &mut Tui) {
+        let Some(stream) = self.quality_list_state.selected().and_then(|i| self.available_streams.get(i)) else { return };
+        let (link, quality_name) = (stream.link.clone(), stream.name.clone());
+
+        self.is_loading = true;
+        self.set_status(format!("Extracting stream URL ({})...", quality_name));
+        let result = self.with_loading(terminal, self.client.extract_stream_url(&link)).await;
+        self.is_loading = false;
+        let url = match result {
+            Ok(url) => url,
+            Err(e) => {
+                self.show_error(format!("Failed to extract stream: {}", e));
+                return;
+            }
+        };
+
+        let copied = match self.clipboard.as_mut() {
+            Some(clipboard) => clipboard.set_text(url),
+            None => arboard::Clipboard::new().and_then(|mut clipboard| {
+                clipboard.set_text(url)?;
+                self.clipboard = Some(clipboard);
+                Ok(())
+            }),
+        };
+        self.set_status(match copied {
+            Ok(()) => "Copied stream URL to clipboard".to_string(),
+            Err(e) => format!("Clipboard unavailable: {}", e),
+        });
+    }
+
+    fn download_dir(&self) -> PathBuf {
+        match self.config.download_dir.as_deref().filter(|d| !d.trim().is_empty()) {
+            Some(dir) => PathBuf::from(dir),
+            None => dirs::video_dir()
+                .or_else(dirs::download_dir)
+                .unwrap_or_else(|| PathBuf::from("."))
+                .join("Enuma"),
+        }
+    }
+
+    /// Downloads the highlighted episode in the preferred (or best) quality.
+    async fn download_episode(&mut self, terminal: &mut Tui) {
+        let Some(anime) = self.selected_anime.clone() else { return };
+        let Some(ep) = self.episode_list_state.selected().and_then(|i| self.episode_list.get(i)).cloned() else { return };
+
+        self.is_loading = true;
+        self.set_status(format!("Fetching streams for Ep {}...", ep.episode));
+        let result = self.with_loading(terminal, self.client.get_stream(&anime.session, &ep.session)).await;
+        self.is_loading = false;
+        let streams = match result {
+            Ok(streams) => streams,
+            Err(e) => {
+                self.set_status(format!("Error fetching stream: {}", e));
+                return;
+            }
+        };
+        let preferred = preferred_stream(&streams, &self.config.quality_order())
+            .and_then(|i| streams.get(i));
+        let Some(stream) = preferred.or_else(|| best_stream(&streams)).cloned() else {
+            self.set_status("No streams found.".to_string());
+            return;
+        };
+        self.start_download(terminal, &anime.title, &ep.episode, stream).await;
+    }
+
+    /// Downloads the quality highlighted on the selection screen.
+    async fn download_selected_stream(&mut self, terminal: &mut Tui) {
+        let Some((anime, _, ep_num)) = self.temp_play_data.clone() else { return };
+        let Some(stream) = self.quality_list_state.selected().and_then(|i| self.available_streams.get(i)).cloned() else { return };
+        self.start_download(terminal, &anime.title, &ep_num, stream).await;
+    }
+
+    /// Extracts the stream and hands it to ffmpeg in the background; progress shows in the status bar.
+    async fn start_download(&mut self, terminal: &mut Tui, title: &str, ep: &str, stream: StreamItem) {
+        let path = match episode_file(&self.download_dir(), title, ep, "mp4") {
+            Ok(path) => path,
+            Err(e) => {
+                self.set_status(format!("Download failed: {:#}", e));
+                return;
+            }
+        };
+        if self.downloads.contains_key(&path) {
+            self.set_status(format!("Ep {} is already downloading", ep));
+            return;
+        }
+
+        self.is_loading = true;
+        self.set_status(format!("Extracting stream URL ({})...", stream.name));
+        let result = self.with_loading(terminal, self.client.extract_stream_url(&stream.link)).await;
+        self.is_loading = false;
+        let url = match result {
+            Ok(url) => url,
+            Err(e) => {
+                self.show_error(format!("Failed to extract stream: {}", e));
+                return;
+            }
+        };
+
+        self.downloads.insert(path.clone(), Transfer::default());
+        self.set_status(format!("Downloading Ep {} ({}) to {}", ep, stream.name, path.display()));
+        let tx = self.download_tx.clone();
+        let name = format!("{} Ep {}", title, ep);
+        let notifications = self.config.notifications;
+        tokio::spawn(async move {
+            let result = download::download_stream(&url, &path, &tx).await.map_err(|e| format!("{:#}", e));
+            if notifications {
+                notify(match &result {
+                    Ok(()) => format!("Finished downloading {}", name),
+                    Err(e) => format!("Download of {} failed: {}", name, e),
+                });
+            }
+            let _ = tx.send(DownloadEvent::Finished { path, result });
+        });
+    }
+
+    /// Downloads every episode on the current page that isn't downloaded yet.
+    fn start_batch_download(&mut self) {
+        let Some(anime) = self.selected_anime.clone() else { return };
+        let episodes = self.episode_list.clone();
+        let message = match self.queue_downloads(anime, &episodes) {
+            Ok(0) => "Every episode on this page is already downloaded".to_string(),
+            Ok(n) => format!("Downloading {} episodes to {}", n, self.download_dir().display()),
+            Err(e) => format!("{:#}", e),
+        };
+        self.set_status(message);
+    }
+
+    /// Downloads the episodes numbered in `numbers`, loading whichever pages hold them.
+    /// Numbers with no episode (not aired yet, or never existed) are reported and skipped.
+    async fn download_range(&mut self, terminal: &mut Tui, numbers: &[u32]) -> Result<()> {
+        let anime = self.selected_anime.clone().context("No anime selected")?;
+        self.is_loading = true;
+        self.set_status(format!("Finding {} episodes of '{}'...", numbers.len(), anime.title));
+
+        let first = self.with_loading(terminal, self.client.get_episodes(&anime.session, 1)).await?;
+        let total_pages = first.total_pages.max(1);
+        let mut pages: Vec<u32> = numbers.iter().map(|&n| page_for_episode(&first, n)).collect();
+        pages.dedup();
+        let mut loaded = HashMap::new();
+        loaded.insert(1, first);
+        let mut found: HashMap<u32, Episode> = HashMap::new();
+
+        // The page estimate assumes even numbering, so numbers it misses get one more look
+        // on the neighbouring pages.
+        for retry in [false, true] {
+            let step_total = pages.len().max(1) as f64;
+            for (step, page) in pages.into_iter().enumerate() {
+                self.progress = Some(step as f64 / step_total);
+                if let std::collections::hash_map::Entry::Vacant(slot) = loaded.entry(page) {
+                    self.set_status(format!("Finding episodes of '{}' (page {}/{})...", anime.title, page, total_pages));
+                    slot.insert(self.with_loading(terminal, self.client.get_episodes(&anime.session, page)).await?);
+                }
+                for ep in &loaded[&page].episodes {
+                    let Ok(n) = ep.episode.parse::<u32>() else { continue };
+                    if numbers.binary_search(&n).is_ok() {
+                        found.entry(n).or_insert_with(|| ep.clone());
+                    }
+                }
+            }
+            if retry { break; }
+            let mut missing_pages: Vec<u32> = numbers.iter()
+                .filter(|n| !found.contains_key(n))
+                .flat_map(|&n| {
+                    let page = page_for_episode(&loaded[&1], n);
+                    [page.saturating_sub(1), page + 1]
+                })
+                .filter(|&p| p >= 1 && p <= total_pages && !loaded.contains_key(&p))
+                .collect();
+            missing_pages.sort_unstable();
+            missing_pages.dedup();
+            pages = missing_pages;
+        }
+        self.is_loading = false;
+
+        let missing: Vec<String> = numbers.iter().filter(|n| !found.contains_key(n)).map(u32::to_string).collect();
+        if found.is_empty() {
+            anyhow::bail!("None of those episodes are available for '{}'", anime.title);
+        }
+        let mut episodes: Vec<Episode> = found.into_values().collect();
+        episodes.sort_by_key(|ep| ep.episode.parse::<u32>().unwrap_or_default());
+        let count = episodes.len();
+        let queued = self.queue_downloads(anime, &episodes)?;
+        let mut message = match queued {
+            0 => format!("All {} episodes are already downloaded", count),
+            n => format!("Downloading {} episodes to {}", n, self.download_dir().display()),
+        };
+        if !missing.is_empty() {
+            message.push_str(&format!(" (not available: Ep {})", missing.join(", ")));
+        }
+        self.set_status(message);
+        Ok(())
+    }
+
+    /// Starts a batch of every episode in `episodes` that isn't fully downloaded yet, fetching
+    /// them one at a time in the background. A failed episode is marked and skipped. Returns how
+    /// many were queued.
+    fn queue_downloads(&mut self, anime: Anime, episodes: &[Episode]) -> Result<usize> {
+        if self.batch.iter().any(|b| matches!(b.status, BatchStatus::Queued | BatchStatus::Active(_))) {
+            anyhow::bail!("A batch download is already running");
+        }
+
+        let dir = self.download_dir();
+        self.batch.clear();
+        let mut jobs = Vec::new();
+        for ep in episodes {
+            let path = match episode_file(&dir, &anime.title, &ep.episode, "mp4") {
+                Ok(path) => path,
+                Err(e) => {
+                    self.batch.clear();
+                    return Err(e.context("Download failed"));
+                }
+            };
+            // Unfinished downloads only exist as `.part` files, so they are fetched again.
+            if path.is_file() || self.downloads.contains_key(&path) { continue; }
+            jobs.push((ep.session.clone(), ep.episode.clone(), path.clone()));
+            self.batch.push(BatchItem { ep: ep.episode.clone(), path, status: BatchStatus::Queued });
+        }
+        let Some(first) = self.batch.first_mut() else { return Ok(0) };
+        first.status = BatchStatus::Active(Transfer::default());
+        let queued = jobs.len();
+
+        let client = self.client.clone();
+        let tx = self.download_tx.clone();
+        let preferred = self.config.quality_order();
+        let notifications = self.config.notifications;
+        tokio::spawn(async move {
+            let mut failed = Vec::new();
+            for (i, (ep_session, ep, path)) in jobs.iter().enumerate() {
+                let result = fetch_episode(&client, &anime.session, ep_session, &preferred, path, &tx).await
+                    .map_err(|e| format!("{:#}", e));
+                if result.is_err() {
+                    failed.push(ep.as_str());
+                }
+                // The last episode is covered by the summary below.
+                if notifications && result.is_ok() && i + 1 < jobs.len() {
+                    notify(format!("Finished downloading {} Ep {}", anime.title, ep));
+                }
+                let _ = tx.send(DownloadEvent::Finished { path: path.clone(), result });
+            }
+            if notifications {
+                let done = jobs.len() - failed.len();
+                notify(match failed.is_empty() {
+                    true => format!("Finished downloading {}: {} episode(s)", anime.title, done),
+                    false => format!("Finished downloading {}: {}/{} episodes ({} failed: Ep {})", anime.title, done, jobs.len(), failed.len(), failed.join(", ")),
+                });
+            }
+        });
+        Ok(queued)
+    }
+
+    /// Records a finished batch episode, moves on to the next, and summarises at the end.
+    fn finish_batch_item(&mut self, index: usize, result: Result<(), String>) {
+        self.batch[index].status = match result {
+            Ok(()) => BatchStatus::Done,
+            Err(e) => BatchStatus::Failed(e),
+        };
+        if let Some(next) = self.batch.iter_mut().find(|b| matches!(b.status, BatchStatus::Queued)) {
+            next.status = BatchStatus::Active(Transfer::default());
+            return;
+        }
+        let failed: Vec<&str> = self.batch.iter()
+            .filter(|b| matches!(b.status, BatchStatus::Failed(_)))
+            .map(|b| b.ep.as_str())
+            .collect();
+        let total = self.batch.len();
+        self.set_status(if failed.is_empty() {
+            format!("Downloaded {}/{}", total, total)
+        } else {
+            format!("Downloaded {}/{} ({} failed: Ep {})", total - failed.len(), total, failed.len(), failed.join(", "))
+        });
+        self.batch.clear();
+    }
+
+    fn poll_downloads(&mut self) {
+        let mut finished = false;
+        while let Ok(event) = self.download_rx.try_recv() {
+            finished |= matches!(event, DownloadEvent::Finished { .. });
+            match event {
+                DownloadEvent::Progress { path, done, total } => {
+                    let transfer = Transfer { done, total };
+                    match self.batch.iter_mut().find(|b| b.path == path) {
+                        Some(item) => item.status = BatchStatus::Active(transfer),
+                        None => { self.downloads.insert(path, transfer); }
+                    }
+                }
+                DownloadEvent::Finished { path, result } if self.batch.iter().any(|b| b.path == path) => {
+                    let index = self.batch.iter().position(|b| b.path == path).unwrap_or_default();
+                    self.finish_batch_item(index, result);
+                }
+                DownloadEvent::Finished { path, result } => {
+                    self.downloads.remove(&path);
+                    self.set_status(match result {
+                        Ok(()) => format!("Downloaded {}", path.display()),
+                        Err(e) => format!("Download of {} failed: {}", path.display(), e),
+                    });
+                }
+            }
+        }
+        if finished {
+            if self.offline_only {
+                self.refilter_episodes();
+            } else {
+                self.refresh_offline();
+            }
+        }
+    }
+
+    /// Pulls the AniList Watching/Planning lists into the library, resolving each title to an
+    /// animepahe entry through a search. Titles without an exact romaji or English match are
+    /// skipped and listed.
+    async fn sync_anilist(&mut self, terminal: &mut Tui) -> Result<()> {
+        let token = self.config.anilist_token.clone().filter(|t| !t.trim().is_empty())
+            .context("Set anilist_token in config.toml to sync with AniList")?;
+        let anilist = anilist::AniListClient::new(&token, &self.config.backend)?;
+
+        self.is_loading = true;
+        self.set_status("Fetching AniList watchlist...".to_string());
+        let entries = self.with_loading(terminal, anilist.watchlist()).await?;
+
+        let known: HashSet<String> = self.library.iter().map(|a| normalize_title(&a.title)).collect();
+        let (mut added, mut unresolved) = (0, Vec::new());
+        let count = entries.len().max(1) as f64;
+        for (i, entry) in entries.into_iter().enumerate() {
+            self.progress = Some(i as f64 / count);
+            let candidates: Vec<&str> = std::iter::once(entry.romaji.as_str()).chain(entry.english.as_deref()).collect();
+            if candidates.iter().any(|t| known.contains(&normalize_title(t))) {
+                continue;
+            }
+            self.set_status(format!("Resolving '{}'...", entry.romaji));
+            // Only an exact title match counts; a merely similar first hit is often a sequel
+            // or an unrelated show, so those are reported as unresolved instead.
+            let wanted: Vec<String> = candidates.iter().map(|t| normalize_title(t)).collect();
+            let mut found = None;
+            for title in &candidates {
+                let Ok(res) = self.with_loading(terminal, self.client.search(title)).await else { continue };
+                found = res.data.into_iter().find(|a| wanted.contains(&normalize_title(&a.title)));
+                if found.is_some() { break; }
+            }
+            match found {
+                Some(anime) if !self.library.iter().any(|a| a.session == anime.session) => {
+                    self.library.push(anime);
+                    added += 1;
+                }
+                Some(_) => {}
+                None => unresolved.push(entry.romaji),
+            }
+        }
+        self.is_loading = false;
+
+        let _ = Self::save_data("library.json", &self.library);
+        if self.library_list_state.selected().is_none() && !self.library.is_empty() {
+            self.library_list_state.select(Some(0));
+        }
+        self.set_status(if unresolved.is_empty() {
+            format!("AniList sync: added {} anime", added)
+        } else {
+            format!("AniList sync: added {}, couldn't find {}: {}", added, unresolved.len(), unresolved.join(", "))
+        });
+        Ok(())
+    }
+
+    /// Adds an anime to the library from a pasted animepahe URL or session,
+    /// confirming it exists (and learning its title) via its first episode page.
+    async fn add_by_reference(&mut self, terminal: &mut Tui, input: &str) -> Result<()> {
+        let session = parse_anime_ref(input)?;
+        if let Some(existing) = self.library.iter().find(|a| a.session == session) {
+            self.set_status(format!("'{}' is already in your library", existing.title));
+            return Ok(());
+        }
+
+        self.is_loading = true;
+        self.set_status("Looking up anime...".to_string());
+        let series = self.with_loading(terminal, self.client.get_episodes(&session, 1)).await?;
+        self.is_loading = false;
+
+        self.set_status(format!("Added '{}' to library", series.title));
+        self.library.push(anime_from_series(session, series.title));
+        let _ = Self::save_data("library.json", &self.library);
+        Ok(())
+    }
+
+    /// Plays a pasted kwik link directly, or opens a pasted series on its episode list.
+    async fn open_url(&mut self, terminal: &mut Tui, input: &str) -> Result<()> {
+        match parse_pasted_url(input)? {
+            PastedUrl::Kwik { url, id } => {
+                self.is_loading = true;
+                self.set_status("Extracting stream URL...".to_string());
+                let direct_url = self.with_loading(terminal, self.client.extract_stream_url(&url)).await?;
+                self.is_loading = false;
+                let title = format!("kwik {}", id);
+                self.launch_player(terminal, &direct_url, &title, None, "-", &id).await?;
+            }
+            PastedUrl::Anime(session) => self.open_series(terminal, session).await?,
+        }
+        Ok(())
+    }
+
+    /// Opens the episode list of a series known only by its session.
+    async fn open_series(&mut self, terminal: &mut Tui, session: String) -> Result<()> {
+        self.is_loading = true;
+        self.set_status("Looking up anime...".to_string());
+        let series = self.with_loading(terminal, self.client.get_episodes(&session, 1)).await?;
+        // Seed the page cache so load_episodes doesn't fetch page 1 a second time.
+        self.selected_anime = Some(anime_from_series(session.clone(), series.title.clone()));
+        self.episode_pages.insert((session, series.page), series);
+        self.load_episodes(terminal, 1).await;
+        Ok(())
+    }
+
+    /// Handles `session:` and `id:` searches. The backend can't look ids up, so an id only
+    /// opens a show already seen in the library, history or earlier results.
+    async fn open_direct(&mut self, terminal: &mut Tui, target: Result<DirectRef>) -> Result<()> {
+        match target? {
+            DirectRef::Session(session) => self.open_series(terminal, session).await,
+            DirectRef::Id(id) => {
+                let known = self.library.iter()
+                    .chain(self.history.iter().map(|h| &h.anime))
+                    .chain(self.search_results.iter())
+                    .chain(self.landing.iter())
+                    .chain(self.search_cache.values().flat_map(|res| res.data.iter()))
+                    .find(|a| a.id == id)
+                    .cloned()
+                    .with_context(|| format!("id {} isn't in your library, history or recent results; search session:<session> from its anime page URL instead", id))?;
+                self.selected_anime = Some(known);
+                self.load_episodes(terminal, 1).await;
+                Ok(())
+            }
+        }
+    }
+
+    /// Re-runs the last failed network operation with the same inputs.
+    async fn retry_last(&mut self, terminal: &mut Tui) -> Result<()> {
+        let Some(action) = self.last_action.take() else { return Ok(()) };
+        match action {
+            RetryableAction::Search(query, page) => {
+                self.search_query = query;
+                self.fetch_search_page(terminal, page).await;
+            }
+            RetryableAction::LoadEpisodes(anime, page) => {
+                self.selected_anime = Some(anime);
+                self.load_episodes(terminal, page).await;
+            }
+            RetryableAction::FetchStreams(anime, ep_session, ep_num) => {
+                self.prepare_stream_selection(terminal, anime, ep_session, ep_num, false).await?;
+            }
+            RetryableAction::Extract(idx) => {
+                self.navigate(CurrentScreen::QualitySelection);
+                self.quality_list_state.select(Some(idx));
+                self.play_selected_stream(terminal).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Shows what's playing on Discord when `discord_rpc` is enabled, or clears it for `None`.
+    /// Connects lazily; a missing Discord is not an error worth interrupting playback for.
+    async fn update_presence(&mut self, watching: Option<(&str, &str)>) -> Result<()> {
+        if !self.config.discord_rpc { return Ok(()); }
+        if self.discord.is_none() {
+            if watching.is_none() { return Ok(()); }
+            let client_id = self.config.discord_client_id.as_deref().filter(|id| !id.trim().is_empty())
+                .context("discord_rpc needs discord_client_id in config.toml")?;
+            let presence = tokio::time::timeout(Duration::from_secs(2), discord::Presence::connect(client_id)).await
+                .context("Timed out connecting to Discord")??;
+            self.discord = Some(presence);
+        }
+        let Some(presence) = self.discord.take() else { return Ok(()) };
+        let update = async {
+            match watching {
+                Some((title, ep)) => presence.set_watching(title, ep).await,
+                None => presence.clear().await,
+            }
+        };
+        // A failed or stuck update drops the client, so the next one reconnects.
+        self.discord = Some(tokio::time::timeout(Duration::from_secs(2), update).await
+            .unwrap_or_else(|_| Err(anyhow::anyhow!("Discord did not respond")))?);
+        Ok(())
+    }
+
+    /// Hands the terminal to the player until it exits, resuming from the saved position
+    /// unless a start time was picked explicitly.
+    async fn launch_player(&mut self, terminal: &mut Tui, url: &str, title: &str, anime_session: Option<&str>, ep: &str, ep_session: &str) -> Result<PlaybackOutcome> {
+        let start = self.start_at.take().or_else(|| self.saved_position(ep_session));
+        let watch_later = watch_later_dir(ep_session);
+        let mut command = match player_command(&self.config, url, title, anime_session, ep, start, &watch_later) {
+            Ok(command) => command,
+            Err(e) => {
+                self.set_status(format!("Not playing, check config.toml: {}", e));
+                return Ok(PlaybackOutcome::default());
+            }
+        };
+        let player = self.config.player().to_string();
+
+        execute!(terminal.backend_mut(), LeaveAlternateScreen, DisableMouseCapture)?;
+        disable_raw_mode()?;
+        terminal.show_cursor()?;
+
+        let started = std::time::Instant::now();
+        let mut outcome = PlaybackOutcome::default();
+        self.player_running.store(true, Ordering::SeqCst);
+        let status = command.status().await;
+        self.player_running.store(false, Ordering::SeqCst);
+        match status {
+            Ok(status) => {
+                outcome.played = true;
+                let saved = take_saved_playback(&watch_later);
+                outcome.watched = counts_as_watched(&saved, status.success(), started.elapsed(), self.config.watched_fraction());
+                outcome.position_secs = saved.position_secs;
+                if status.success() {
+                    self.set_status(match (outcome.position_secs, outcome.watched) {
+                        (Some(secs), false) => format!("Stopped Ep {} at {}, it will resume there.", ep, format_timestamp(secs)),
+                        (Some(secs), true) => format!("Stopped Ep {} at {}, counted as watched.", ep, format_timestamp(secs)),
+                        (None, _) => format!("Finished playing Ep {}.", ep),
+                    });
+                } else {
+                    self.set_status(format!("{} exited with status: {}", player, status));
+                }
+            },
+            Err(e) => {
+                self.set_status(format!("Failed to launch {}: {}. Is it installed?", player, e));
+            }
+        }
+
+        if let Some(hook) = self.config.post_play_command.as_deref().filter(|c| !c.trim().is_empty()) {
+            if let Err(e) = spawn_post_play_hook(hook, title, ep) {
+                self.set_status(format!("{} Post-play hook failed: {}", self.status_message, e));
+            }
+        }
+
+        enable_raw_mode()?;
+        execute!(terminal.backend_mut(), EnterAlternateScreen, EnableMouseCapture)?;
+        terminal.hide_cursor()?;
+        terminal.clear()?;
+        self.last_input = Instant::now();
+        Ok(outcome)
+    }
+
+    /// Asks the terminal which image protocol it speaks, when snapshots are to be drawn as images.
+    fn detect_graphics(&mut self) {
+        if self.config.show_thumbnails {
+            self.picker = thumbnail::detect_picker();
+        }
+    }
+
+    /// The picker to encode snapshots with, when enabled and the terminal has a protocol.
+    fn image_preview(&self) -> Option<&Picker> {
+        self.picker.as_ref().filter(|_| self.config.show_thumbnails)
+    }
+
+    /// Whether an image may be drawn this frame. Popups and the idle dimming go over the
+    /// preview, and graphics would show through them.
+    fn image_visible(&self) -> bool {
+        !self.is_idle() && self.overlay.is_none() && self.confirm.is_none() && self.error_popup.is_none()
+    }
+
+    fn selected_snapshot(&self) -> Option<&str> {
+        self.episode_list_state.selected()
+            .and_then(|i| self.episode_list.get(i))
+            .map(|ep| ep.snapshot.as_str())
+            .filter(|u| !u.is_empty())
+    }
+
+    /// Starts converting the highlighted episode's snapshot in the background if it isn't cached.
+    fn request_thumbnail(&mut self) {
+        while let Ok((url, art)) = self.thumb_rx.try_recv() {
+            self.thumb_pending.remove(&url);
+            self.thumbnails.insert(url, art);
+        }
+        while let Ok((url, image)) = self.image_rx.try_recv() {
+            self.image_pending.remove(&url);
+            let protocol = image.zip(self.picker.as_ref()).map(|(image, picker)| picker.new_resize_protocol(image));
+            self.images.insert(url, protocol);
+        }
+
+        if self.current_screen != CurrentScreen::EpisodeList { return; }
+        let Some(url) = self.selected_snapshot().map(str::to_string) else { return };
+
+        if self.image_preview().is_some() {
+            if self.images.contains_key(&url) || !self.image_pending.insert(url.clone()) {
+                return;
+            }
+            let tx = self.image_tx.clone();
+            tokio::spawn(async move {
+                let image = thumbnail::preview_image(&url).await.ok();
+                let _ = tx.send((url, image));
+            });
+            return;
+        }
+
+        if !self.config.ascii_thumbnails { return; }
+        let (cols, rows) = self.thumb_size;
+        if cols == 0 || rows == 0 { return; }
+        if self.thumbnails.contains_key(&url) || !self.thumb_pending.insert(url.clone()) {
+            return;
+        }
+
+        let tx = self.thumb_tx.clone();
+        tokio::spawn(async move {
+            let art = thumbnail::ascii_art(&url, cols, rows).await.ok();
+            let _ = tx.send((url, art));
+        });
+    }
+
+    /// Moves to `screen`, remembering where we came from. Revisiting a screen already
+    /// on the stack unwinds back to it so the history never loops.
+    fn navigate(&mut self, screen: CurrentScreen) {
+        if self.current_screen == screen { return; }
+        self.list_filter.clear();
+        self.marked.clear();
+        if let Some(pos) = self.screen_stack.iter().position(|s| *s == screen) {
+            self.screen_stack.truncate(pos);
+        } else {
+            self.screen_stack.push(self.current_screen.clone());
+        }
+        self.current_screen = screen;
+    }
+
+    fn go_back(&mut self) {
+        self.list_filter.clear();
+        self.marked.clear();
+        self.current_screen = self.screen_stack.pop().unwrap_or(CurrentScreen::Search);
+    }
+
+    /// Whether the UI has gone untouched past `idle_timeout_secs`. Never true while loading.
+    fn is_idle(&self) -> bool {
+        match self.config.idle_timeout_secs {
+            Some(secs) if secs > 0 && !self.is_loading => self.last_input.elapsed() >= Duration::from_secs(secs),
+            _ => false,
+        }
+    }
+
+    /// Indices into `search_results`, `library` or `history` in the order shown on `screen`:
+    /// the fuzzy matches for `list_filter` best first while Library or History is being
+    /// filtered, then `sort_mode` for results and the library. History stays newest first.
+    fn list_view(&self, screen: CurrentScreen) -> Vec<usize> {
+        let items: Vec<&Anime> = match screen {
+            CurrentScreen::SearchResults => self.search_results.iter().collect(),
+            CurrentScreen::Library => self.library.iter().collect(),
+            CurrentScreen::History => self.history.iter().map(|h| &h.anime).collect(),
+            _ => return Vec::new(),
+        };
+        let filtering = !self.list_filter.trim().is_empty() && self.current_screen == screen
+            && screen != CurrentScreen::SearchResults;
+        let mut view: Vec<usize> = if filtering {
+            let mut scored: Vec<(usize, i64)> = items.iter().enumerate()
+                .filter_map(|(i, a)| fuzzy_score(&self.list_filter, &a.title).map(|score| (i, score)))
+                .collect();
+            scored.sort_by_key(|&(_, score)| std::cmp::Reverse(score));
+            scored.into_iter().map(|(i, _)| i).collect()
+        } else {
+            (0..items.len()).collect()
+        };
+        if screen != CurrentScreen::History {
+            self.sort_mode.apply(&items, &mut view);
+        }
+        view
+    }
+
+    /// Index into `search_results` of the highlighted row.
+    fn search_index(&self) -> Option<usize> {
+        let i = self.search_list_state.selected()?;
+        self.list_view(CurrentScreen::SearchResults).get(i).copied()
+    }
+
+    /// Cycles the sort order, keeping the highlighted entry highlighted.
+    fn cycle_sort(&mut self) {
+        let screen = self.current_screen.clone();
+        let current = match screen {
+            CurrentScreen::SearchResults => self.search_index(),
+            CurrentScreen::Library => self.library_index(),
+            _ => return,
+        };
+        self.sort_mode = self.sort_mode.next();
+        let row = current.and_then(|c| self.list_view(screen.clone()).iter().position(|&i| i == c));
+        let state = match screen {
+            CurrentScreen::SearchResults => &mut self.search_list_state,
+            _ => &mut self.library_list_state,
+        };
+        state.select(row.or(Some(0)));
+        self.set_status(match self.sort_mode {
+            SortMode::Relevance => "Sorted by relevance".to_string(),
+            mode => format!("Sorted {}", mode.suffix().trim()),
+        });
+    }
+
+    /// Highlights a random row of the (filtered) search results or library.
+    fn pick_random(&mut self) {
+        let screen = self.current_screen.clone();
+        let view = self.list_view(screen.clone());
+        if view.is_empty() {
+            self.set_status("Nothing to pick from".to_string());
+            return;
+        }
+        let row = rand::rng().random_range(0..view.len());
+        let (title, state) = match screen {
+            CurrentScreen::SearchResults => (&self.search_results[view[row]].title, &mut self.search_list_state),
+            CurrentScreen::Library => (&self.library[view[row]].title, &mut self.library_list_state),
+            _ => return,
+        };
+        state.select(Some(row));
+        self.set_status(format!("How about '{}'? Enter to view episodes, 'R' to pick again.", title));
+    }
+
+    /// Index into `library` of the highlighted row.
+    fn library_index(&self) -> Option<usize> {
+        let i = self.library_list_state.selected()?;
+        self.list_view(CurrentScreen::Library).get(i).copied()
+    }
+
+    /// Index into `history` of the highlighted row.
+    fn history_index(&self) -> Option<usize> {
+        let i = self.history_list_state.selected()?;
+        self.list_view(CurrentScreen::History).get(i).copied()
+    }
+
+    /// Number of rows on the current screen's list, or `None` on screens without one.
+    fn list_len(&self) -> Option<usize> {
+        match self.current_screen {
+            CurrentScreen::Search => Some(self.landing.len()).filter(|&len| len > 0),
+            CurrentScreen::Info => None,
+            CurrentScreen::SearchResults => Some(self.search_results.len()),
+            CurrentScreen::Library | CurrentScreen::History => Some(self.list_view(self.current_screen.clone()).len()),
+            CurrentScreen::EpisodeList => Some(self.episode_list.len()),
+            CurrentScreen::QualitySelection => Some(self.available_streams.len()),
+            CurrentScreen::Queue => Some(self.queue.len()),
+            CurrentScreen::Settings => Some(SETTINGS.len()),
+        }
+    }
+
+    /// Moves the selection to the first (`top`) or last row of the current list.
+    fn jump_selection(&mut self, top: bool) {
+        let Some(len) = self.list_len().filter(|&len| len > 0) else { return };
+        let state = match self.current_screen {
+            CurrentScreen::Search => &mut self.landing_list_state,
+            CurrentScreen::SearchResults => &mut self.search_list_state,
+            CurrentScreen::Library => &mut self.library_list_state,
+            CurrentScreen::History => &mut self.history_list_state,
+            CurrentScreen::EpisodeList => &mut self.episode_list_state,
+            CurrentScreen::QualitySelection => &mut self.quality_list_state,
+            CurrentScreen::Queue => &mut self.queue_list_state,
+            CurrentScreen::Settings => &mut self.settings_list_state,
+            CurrentScreen::Info => return,
+        };
+        state.select(Some(if top { 0 } else { len - 1 }));
+    }
+
+    fn list_state_mut(&mut self) -> Option<&mut ListState> {
+        match self.current_screen {
+            CurrentScreen::Library => Some(&mut self.library_list_state),
+            CurrentScreen::History => Some(&mut self.history_list_state),
+            _ => None,
+        }
+    }
+
+    /// Re-applies the filter after an edit, highlighting the best match.
+    fn refilter(&mut self) {
+        let empty = self.list_view(self.current_screen.clone()).is_empty();
+        if let Some(state) = self.list_state_mut() {
+            state.select(if empty { None } else { Some(0) });
+        }
+    }
+
+    /// Drops the filter, keeping the highlighted entry highlighted in the full list.
+    fn clear_filter(&mut self) {
+        let current = match self.current_screen {
+            CurrentScreen::Library => self.library_index(),
+            CurrentScreen::History => self.history_index(),
+            _ => None,
+        };
+        self.list_filter.clear();
+        if let Some(state) = self.list_state_mut() {
+            state.select(current.or(Some(0)));
+        }
+    }
+
+    /// Opens the detail screen for the highlighted anime, fetching `method=info` the first
+    /// time. A backend without that endpoint just leaves the screen with what we already know.
+    async fn show_info(&mut self, terminal: &mut Tui) {
+        let Some(anime) = self.highlighted_window().first().map(|a| (*a).clone()) else { return };
+        if !self.info.contains_key(&anime.session) {
+            self.set_status(format!("Fetching details for '{}'...", anime.title));
+            let result = self.with_loading(terminal, self.client.get_info(&anime.session)).await;
+            let info = result.ok().filter(|info| !info.is_empty());
+            self.info.insert(anime.session.clone(), info);
+        }
+        self.set_status(match self.info.get(&anime.session) {
+            Some(Some(_)) => "Up/Down to scroll, Esc to go back".to_string(),
+            _ => "No extra details from the backend. Esc to go back".to_string(),
+        });
+        self.info_anime = Some(anime);
+        self.info_scroll = 0;
+        self.navigate(CurrentScreen::Info);
+    }
+
+    /// The highlighted anime followed by its next and previous neighbours in the current list.
+    fn highlighted_window(&self) -> Vec<&Anime> {
+        let (items, selected): (Vec<&Anime>, Option<usize>) = match self.current_screen {
+            CurrentScreen::SearchResults => (
+                self.list_view(CurrentScreen::SearchResults).into_iter().map(|i| &self.search_results[i]).collect(),
+                self.search_list_state.selected(),
+            ),
+            CurrentScreen::Library => (
+                self.list_view(CurrentScreen::Library).into_iter().map(|i| &self.library[i]).collect(),
+                self.library_list_state.selected(),
+            ),
+            CurrentScreen::History => (
+                self.list_view(CurrentScreen::History).into_iter().map(|i| &self.history[i].anime).collect(),
+                self.history_list_state.selected(),
+            ),
+            _ => return Vec::new(),
+        };
+        let Some(i) = selected.filter(|&i| i < items.len()) else { return Vec::new() };
+
+        let mut window = vec![items[i]];
+        if let Some(next) = items.get(i + 1) { window.push(next); }
+        if let Some(prev) = i.checked_sub(1).and_then(|p| items.get(p)) { window.push(prev); }
+        window
+    }
+
+    /// Looks up Jikan metadata for the highlighted anime and its neighbours in the background.
+    /// Lookups only start once the selection has rested for `METADATA_DEBOUNCE`, and any still
+    /// in flight for items that scrolled out of the window are aborted.
+    fn request_metadata(&mut self) {
+        while let Ok((session, meta)) = self.metadata_rx.try_recv() {
+            self.metadata_tasks.remove(&session);
+            self.metadata.insert(session, meta);
+        }
+
+        if !self.config.enrich_metadata { return; }
+        let wanted: Vec<(String, String)> = self.highlighted_window().into_iter()
+            .map(|a| (a.session.clone(), a.title.clone()))
+            .collect();
+        let Some((current, _)) = wanted.first() else { return };
+
+        match &self.metadata_focus {
+            Some((session, since)) if session == current => {
+                if since.elapsed() < METADATA_DEBOUNCE { return; }
+            }
+            _ => {
+                self.metadata_focus = Some((current.clone(), Instant::now()));
+                self.metadata_tasks.retain(|session, task| {
+                    let keep = wanted.iter().any(|(s, _)| s == session);
+                    if !keep { task.abort(); }
+                    keep
+                });
+                return;
+            }
+        }
+
+        for (session, title) in wanted {
+            if self.metadata.contains_key(&session) || self.metadata_tasks.contains_key(&session) {
+                continue;
+            }
+            let jikan = self.jikan.clone();
+            let tx = self.metadata_tx.clone();
+            let key = session.clone();
+            let task = tokio::spawn(async move {
+                let meta = jikan.lookup(&title).await.ok().flatten();
+                let _ = tx.send((session, meta));
+            });
+            self.metadata_tasks.insert(key, task);
+        }
+    }
+
+    /// Looks for the player and ffmpeg on a separate thread, so a slow PATH never holds up
+    /// the first frame; the start screen shows the warning once it arrives.
+    fn check_tools(&mut self) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.tool_rx = Some(rx);
+        let player = self.config.player().to_string();
+        std::thread::spawn(move || {
+            let _ = tx.send(missing_tools_warning(&player));
+        });
+    }
+
+    fn poll_tools(&mut self) {
+        let Some(warning) = self.tool_rx.as_mut().and_then(|rx| rx.try_recv().ok()) else { return };
+        self.tool_rx = None;
+        self.tool_warning = warning;
+    }
+
+    /// Fetches the start screen's list in the background.
+    fn load_landing(&mut self) {
+        let Some(landing) = self.config.landing() else { return };
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.landing_rx = Some(rx);
+        let client = self.client.clone();
+        tokio::spawn(async move {
+            let result = match landing {
+                Landing::Airing => client.get_popular().await,
+                Landing::Query(query) => client.search(&query).await.map(|res| res.data),
+            };
+            let _ = tx.send(result.map_err(|e| format!("{:#}", e)));
+        });
+    }
+
+    fn poll_landing(&mut self) {
+        let Some(result) = self.landing_rx.as_mut().and_then(|rx| rx.try_recv().ok()) else { return };
+        self.landing_rx = None;
+        match result {
+            Ok(list) => {
+                self.landing_list_state.select(if list.is_empty() { None } else { Some(0) });
+                self.landing = list;
+            }
+            Err(e) if self.current_screen == CurrentScreen::Search => {
+                self.set_status(format!("Start screen list unavailable ({}). Set landing in config.toml to a search query, or \"\" to turn it off.", e));
+            }
+            Err(_) => {}
+        }
+    }
+
+    /// Quits on SIGINT (or Ctrl+Break on Windows) as if Esc was pressed on the home screen,
+    /// unless a player has the terminal: Ctrl+C there is meant for the player, which gets it too.
+    fn watch_interrupts(&self) {
+        let player_running = self.player_running.clone();
+        let interrupted = self.interrupted.clone();
+        tokio::spawn(async move {
+            while tokio::signal::ctrl_c().await.is_ok() {
+                if !player_running.load(Ordering::SeqCst) {
+                    interrupted.store(true, Ordering::SeqCst);
+                }
+            }
+        });
+    }
+
+    async fn start_control_socket(&mut self) {
+        let Some(addr) = self.config.control_socket.clone() else { return };
+        match control::spawn(&addr).await {
+            Ok(rx) => self.control_rx = Some(rx),
+            Err(e) => self.set_status(format!("Control socket disabled: {:#}", e)),
+        }
+    }
+
+    async fn handle_control(&mut self, terminal: &mut Tui, command: ControlCommand) -> ControlResponse {
+        match command {
+            ControlCommand::Search { query } => {
+                self.search_query = query;
+                self.search_results.clear();
+                self.perform_search(terminal).await;
+                if self.search_results.is_empty() {
+                    ControlResponse::err(self.status_message.clone())
+                } else {
+                    ControlResponse::ok(self.status_message.clone())
+                }
+            }
+            ControlCommand::Play { title, episode } => {
+                match self.play_by_title(terminal, &title, episode.unwrap_or(1)).await {
+                    Ok(()) => ControlResponse::ok(self.status_message.clone()),
+                    Err(e) => {
+                        self.is_loading = false;
+                        self.set_status(format!("Error: {}", e));
+                        ControlResponse::err(e.to_string())
+                    }
+                }
+            }
+            ControlCommand::Status => ControlResponse::ok(self.status_message.clone()),
+            ControlCommand::Quit => ControlResponse::ok("Bye"),
+        }
+    }
+
+    async fn play_by_title(&mut self, terminal: &mut Tui, title: &str, episode: u32) -> Result<()> {
+        self.is_loading = true;
+        self.set_status(format!("Searching '{}'...", title));
+        let res = self.with_loading(terminal, self.client.search(title)).await?;
+        let anime = res.data.into_iter().next()
+            .with_context(|| format!("No results for '{}'", title))?;
+
+        let ep = self.with_loading(terminal, self.find_episode(&anime.session, episode)).await?;
+        self.prepare_stream_selection(terminal, anime, ep.session, ep.episode, true).await?;
+        if self.current_screen != CurrentScreen::QualitySelection {
+            anyhow::bail!("{}", self.status_message);
+        }
+
+        self.play_selected_stream(terminal).await?;
+        if self.temp_play_data.is_some() {
+            anyhow::bail!("{}", self.status_message);
+        }
+        Ok(())
+    }
+
+    /// Appends every available episode numbered `start` or later to the watch queue,
+    /// skipping ones already queued so re-running it tops up an ongoing series.
+    async fn enqueue_from(&mut self, terminal: &mut Tui, start: u32) -> Result<()> {
+        let anime = self.selected_anime.clone().context("No anime selected")?;
+        self.is_loading = true;
+        self.set_status(format!("Queueing '{}' from Ep {}...", anime.title, start));
+
+        let first = self.with_loading(terminal, self.client.get_episodes(&anime.session, 1)).await?;
+        let total_pages = first.total_pages.max(1);
+        let mut page = page_for_episode(&first, start);
+        let mut res = if page == 1 { first } else { self.with_loading(terminal, self.client.get_episodes(&anime.session, page)).await? };
+
+        let mut added = 0;
+        loop {
+            for ep in res.episodes {
+                let in_range = ep.episode.parse::<f64>().map(|n| n >= start as f64).unwrap_or(false);
+                let queued = self.queue.iter().any(|q| q.episode_session == ep.session);
+                if in_range && !queued {
+                    self.queue.push(QueueItem { anime: anime.clone(), episode_session: ep.session, episode: ep.episode });
+                    added += 1;
+                }
+            }
+            if page >= total_pages { break; }
+            page += 1;
+            self.set_status(format!("Queueing '{}' (page {}/{})...", anime.title, page, total_pages));
+            res = self.with_loading(terminal, self.client.get_episodes(&anime.session, page)).await?;
+        }
+
+        self.is_loading = false;
+        let _ = Self::save_data("queue.json", &self.queue);
+        let airing = anime.status.contains("Airing") && !anime.status.contains("Finished");
+        self.set_status(if airing {
+            format!("Queued {} episodes. Still airing: queue again later to top up.", added)
+        } else {
+            format!("Queued {} episodes.", added)
+        });
+        Ok(())
+    }
+
+    fn enqueue_selected(&mut self) {
+        let Some(anime) = self.selected_anime.clone() else { return };
+        let Some(ep) = self.episode_list_state.selected().and_then(|i| self.episode_list.get(i)).cloned() else { return };
+        if self.queue.iter().any(|q| q.episode_session == ep.session) {
+            self.set_status(format!("Ep {} is already queued", ep.episode));
+            return;
+        }
+        self.set_status(format!("Queued Ep {}", ep.episode));
+        self.queue.push(QueueItem { anime, episode_session: ep.session, episode: ep.episode });
+        let _ = Self::save_data("queue.json", &self.queue);
+    }
+
+    async fn play_from_queue(&mut self, terminal: &mut Tui) -> Result<()> {
+        let Some(i) = self.queue_list_state.selected() else { return Ok(()) };
+        if i >= self.queue.len() { return Ok(()); }
+        let item = self.queue.remove(i);
+        let _ = Self::save_data("queue.json", &self.queue);
+        if self.queue.is_empty() {
+            self.queue_list_state.select(None);
+        } else if i >= self.queue.len() {
+            self.queue_list_state.select(Some(self.queue.len() - 1));
+        }
+        self.prepare_stream_selection(terminal, item.anime, item.episode_session, item.episode, false).await
+    }
+
+    /// Moves focus to an empty search box.
+    fn focus_search(&mut self) {
+        self.focus = Focus::SearchBox;
+        self.search_query.clear();
     }
 
-    fn toggle_library(&mut self) {
-        let session = match self.current_screen {
-            CurrentScreen::SearchResults => {
-                self.search_list_state.selected()
-                    .and_then(|i| self.search_results.get(i))
-                    .map(|a| a.session.as_str())
+    /// Applies Enter on the highlighted setting: choices cycle, the folder opens a prompt.
+    fn change_setting(&mut self) {
+        let Some(&setting) = self.settings_list_state.selected().and_then(|i| SETTINGS.get(i)) else { return };
+        let saved = match setting {
+            Setting::PreferredQuality if !self.config.quality_preference.is_empty() => {
+                self.set_status("quality_preference in config.toml decides the quality; edit it there".to_string());
+                return;
             }
-            CurrentScreen::Library => {
-                self.library_list_state.selected()
-                    .and_then(|i| self.library.get(i))
-                    .map(|a| a.session.as_str())
+            Setting::PreferredQuality => {
+                let current = QUALITY_CHOICES.iter()
+                    .position(|q| *q == self.config.preferred_quality.as_deref())
+                    .unwrap_or(0);
+                let next = QUALITY_CHOICES[(current + 1) % QUALITY_CHOICES.len()];
+                Config::save_key("preferred_quality", next)
+                    .map(|()| self.config.preferred_quality = next.map(str::to_string))
             }
-            CurrentScreen::History => {
-                self.history_list_state.selected()
-                    .and_then(|i| self.history.get(i))
-                    .map(|h| h.anime.session.as_str())
+            Setting::HistoryOn => {
+                let next = match self.config.history_on {
+                    HistoryOn::Start => HistoryOn::Finish,
+                    HistoryOn::Finish => HistoryOn::Start,
+                };
+                Config::save_key("history_on", Some(next))
+                    .map(|()| self.config.history_on = next)
+            }
+            Setting::DiscordRpc => {
+                let next = !self.config.discord_rpc;
+                Config::save_key("discord_rpc", Some(next)).map(|()| {
+                    self.config.discord_rpc = next;
+                    if !next { self.discord = None; }
+                })
+            }
+            Setting::DownloadDir => {
+                let current = self.config.download_dir.clone().unwrap_or_default();
+                self.open_prompt(InputPrompt::DownloadDir, current);
+                return;
+            }
+            Setting::EpisodeCache => {
+                self.episode_pages.clear();
+                self.set_status(match self.client.clear_episode_cache() {
+                    Ok(n) => format!("Cleared {} cached episode pages", n),
+                    Err(e) => format!("Could not clear the episode cache: {:#}", e),
+                });
+                return;
             }
-            _ => None,
         };
+        self.set_status(match saved {
+            Ok(()) => format!("{}: {}", setting.label(), setting.value(&self.config)),
+            Err(e) => format!("Could not save setting: {:#}", e),
+        });
+    }
 
-        let Some(session) = session.map(String::from) else { return };
-
-        if let Some(pos) = self.library.iter().position(|f| f.session == session) {
-            let title = self.library[pos].title.clone();
-            self.library.remove(pos);
-            self.status_message = format!("Removed '{}' from library", title);
-        } else {
-            let anime = match self.current_screen {
-                CurrentScreen::SearchResults => {
-                    self.search_list_state.selected()
-                        .and_then(|i| self.search_results.get(i).cloned())
-                }
-                CurrentScreen::History => {
-                    self.history_list_state.selected()
-                        .and_then(|i| self.history.get(i).map(|h| h.anime.clone()))
-                }
-                _ => None,
-            };
-            if let Some(anime) = anime {
-                self.status_message = format!("Added '{}' to library", anime.title);
-                self.library.push(anime);
-            }
+    /// Checks the folder can be created and written to before saving it as `download_dir`.
+    fn set_download_dir(&mut self, input: &str) -> Result<()> {
+        let dir = input.trim();
+        if dir.is_empty() {
+            Config::save_key::<&str>("download_dir", None)?;
+            self.config.download_dir = None;
+            return Ok(());
         }
-        let _ = Self::save_data("library.json", &self.library);
+        let path = PathBuf::from(dir);
+        std::fs::create_dir_all(&path).with_context(|| format!("cannot create {}", dir))?;
+        let probe = path.join(".enuma-write-test");
+        std::fs::write(&probe, b"").with_context(|| format!("{} is not writable", dir))?;
+        let _ = std::fs::remove_file(probe);
+        Config::save_key("download_dir", Some(dir))?;
+        self.config.download_dir = Some(dir.to_string());
+        Ok(())
     }
 
-    fn record_history(&mut self, anime: Anime, ep_session: String, ep_num: String) {
-        let now = chrono::Local::now().format("%Y-%m-%d %H:%M").to_string();
-        
-        if let Some(pos) = self.history.iter().position(|h| h.anime.session == anime.session) {
-            self.history.remove(pos);
+    /// Writes the library as a MyAnimeList XML export, with the episodes marked watched.
+    fn export_mal(&self, input: &str) -> Result<PathBuf> {
+        let path = PathBuf::from(input.trim());
+        if path.as_os_str().is_empty() {
+            anyhow::bail!("no file given");
         }
-        
-        self.history.insert(0, HistoryItem {
-            anime,
-            episode_session: ep_session,
-            last_episode: ep_num,
-            last_watched: now,
-        });
-        
-        // Keep only top 50
-        if self.history.len() > 50 {
-            self.history.truncate(50);
+        let progress = watch_progress(&self.watched);
+        if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir).with_context(|| format!("cannot create {}", dir.display()))?;
         }
-        
-        let _ = Self::save_data("history.json", &self.history);
+        std::fs::write(&path, mal::export_xml(&self.library, &progress))
+            .with_context(|| format!("cannot write {}", path.display()))?;
+        Ok(path)
     }
 
-    async fn perform_search(&mut self) {
-        if self.search_query.is_empty() { 
-            self.is_searching = false;
-            return; 
-        }
-        self.is_loading = true;
-        self.status_message = "Searching...".to_string();
-        self.is_searching = false;
-        match self.client.search(&self.search_query).await {
-            Ok(res) => {
-                self.is_loading = false;
-                self.search_results = res.data;
-                self.current_screen = CurrentScreen::SearchResults;
-                self.search_list_state.select(Some(0));
-                self.status_message = format!("Found {} results. 'f' to add to library, Enter to view.", self.search_results.len());
-            }
-            Err(e) => {
-                self.is_loading = false;
-                self.status_message = format!("Error: {}", e);
-            }
+    /// Puts `message` in the status bar, and also in a popup when it's too long to read there.
+    fn show_error(&mut self, message: String) {
+        if message.chars().count() + 1 > self.status_width as usize {
+            self.error_popup = Some(message.clone());
         }
+        self.set_status(message);
     }
 
-    async fn load_episodes(&mut self, page: u32) {
-        if let Some(anime) = &self.selected_anime {
-            let session = anime.session.clone();
-            self.is_loading = true;
-            self.status_message = format!("Fetching episodes (Page {})...", page);
-            match self.client.get_episodes(&session, page).await {
-                Ok(res) => {
+    fn open_prompt(&mut self, prompt: InputPrompt, initial: String) {
+        self.focus = Focus::Prompt(prompt);
+        self.input_buffer = initial;
+    }
+
+    async fn submit_prompt(&mut self, terminal: &mut Tui, prompt: InputPrompt) {
+        let input = std::mem::take(&mut self.input_buffer);
+        self.focus = Focus::Content;
+        match prompt {
+            InputPrompt::QueueFrom => match input.trim().parse::<u32>() {
+                Ok(start) => {
+                    if let Err(e) = self.enqueue_from(terminal, start).await {
+                        self.is_loading = false;
+                        self.set_status(format!("Error queueing episodes: {}", e));
+                    }
+                }
+                Err(_) => self.set_status(format!("Invalid episode number: '{}'", input.trim())),
+            },
+            InputPrompt::DownloadRange => match parse_episode_numbers(&input) {
+                Ok(numbers) => {
+                    if let Err(e) = self.download_range(terminal, &numbers).await {
+                        self.is_loading = false;
+                        self.set_status(format!("Download failed: {:#}", e));
+                    }
+                }
+                Err(e) => self.set_status(format!("Invalid episodes '{}': {}", input.trim(), e)),
+            },
+            InputPrompt::StartAt => match parse_timestamp(&input) {
+                Some(secs) => {
+                    self.start_at = Some(secs);
+                    if let Err(e) = self.play_selected_stream(terminal).await {
+                        self.set_status(format!("Error: {}", e));
+                    }
+                    self.start_at = None;
+                }
+                None => self.set_status(format!("Invalid timestamp '{}'. Use mm:ss or hh:mm:ss.", input.trim())),
+            },
+            InputPrompt::AddToLibrary => {
+                if let Err(e) = self.add_by_reference(terminal, &input).await {
                     self.is_loading = false;
-                    self.episode_list = res.episodes;
-                    self.ep_page = res.page;
-                    self.ep_total_pages = res.total_pages;
-                    self.current_screen = CurrentScreen::EpisodeList;
-                    self.episode_list_state.select(Some(0));
-                    self.status_message = format!("Page {}/{}. Left/Right for pages. Enter to play.", self.ep_page, self.ep_total_pages);
+                    self.set_status(format!("Could not add '{}': {}", input.trim(), e));
                 }
-                Err(e) => {
+            }
+            InputPrompt::ClearHistory => {
+                if matches!(input.trim().to_lowercase().as_str(), "y" | "yes") {
+                    self.history.clear();
+                    self.history_list_state.select(None);
+                    self.set_status(match Self::save_data("history.json", &self.history) {
+                        Ok(()) => "History cleared".to_string(),
+                        Err(e) => format!("Could not save history: {:#}", e),
+                    });
+                } else {
+                    self.set_status("History kept".to_string());
+                }
+            }
+            InputPrompt::ExportMal => {
+                self.set_status(match self.export_mal(&input) {
+                    Ok(path) => format!("Exported {} entries to {}. Import it at myanimelist.net/import.php", self.library.len(), path.display()),
+                    Err(e) => format!("Export failed: {:#}", e),
+                });
+            }
+            InputPrompt::DownloadDir => {
+                let message = match self.set_download_dir(&input) {
+                    Ok(()) => format!("Download folder: {}", Setting::DownloadDir.value(&self.config)),
+                    Err(e) => format!("Invalid download folder: {:#}", e),
+                };
+                self.refresh_offline();
+                self.set_status(message);
+            }
+            InputPrompt::OpenUrl => {
+                if let Err(e) = self.open_url(terminal, &input).await {
                     self.is_loading = false;
-                    self.status_message = format!("Error fetching episodes: {}", e);
+                    self.set_status(format!("Could not open '{}': {}", input.trim(), e));
                 }
             }
+            InputPrompt::GoToEpisode => match input.trim().parse::<u32>() {
+                Ok(number) => {
+                    if let Err(e) = self.go_to_episode(terminal, number).await {
+                        self.is_loading = false;
+                        self.set_status(format!("{}", e));
+                    }
+                }
+                Err(_) => self.set_status(format!("Invalid episode number: '{}'", input.trim())),
+            },
         }
     }
 
-    async fn play_episode(&mut self) -> Result<()> {
-        let Some(i) = self.episode_list_state.selected() else { return Ok(()) };
-        let Some(ep) = self.episode_list.get(i) else { return Ok(()) };
-        let ep_session = ep.session.clone();
-        let ep_num = ep.episode.clone();
-        if let Some(anime) = self.selected_anime.clone() {
-            self.prepare_stream_selection(anime, ep_session, ep_num).await?;
+    fn episode_index(&self, number: u32) -> Option<usize> {
+        self.episode_list.iter().position(|e| e.episode.parse::<f64>().ok() == Some(number as f64))
+    }
+
+    /// Loads the page holding episode `number` and highlights it.
+    async fn go_to_episode(&mut self, terminal: &mut Tui, number: u32) -> Result<()> {
+        if let Some(i) = self.episode_index(number) {
+            self.episode_list_state.select(Some(i));
+            return Ok(());
+        }
+        let anime = self.selected_anime.clone().context("No anime selected")?;
+
+        self.is_loading = true;
+        self.set_status(format!("Looking for Ep {}...", number));
+        let first = self.with_loading(terminal, self.client.get_episodes(&anime.session, 1)).await?;
+        let page = page_for_episode(&first, number);
+        self.load_episodes(terminal, page).await;
+        if self.ep_page != page {
+            return Ok(());
+        }
+        match self.episode_index(number) {
+            Some(i) => {
+                self.episode_list_state.select(Some(i));
+                self.set_status(format!("Jumped to Ep {} (page {}/{})", number, self.ep_page, self.ep_total_pages));
+            }
+            None => {
+                let first_ep = first.episodes.first().map(|e| e.episode.as_str()).unwrap_or("?");
+                anyhow::bail!("Episode {} not found; {} starts at Ep {} and has {} page(s)", number, anime.title, first_ep, first.total_pages);
+            }
         }
         Ok(())
     }
 
-    async fn prepare_stream_selection(&mut self, anime: Anime, ep_session: String, ep_num: String) -> Result<()> {
-        self.is_loading = true;
-        self.status_message = format!("Fetching streams for Ep {}...", ep_num);
-        let series_session = anime.session.clone();
+    /// Opens `anime`'s episodes with the first unwatched one highlighted: the lowest-numbered
+    /// episode past the furthest one in history that isn't marked watched, on whichever page
+    /// holds it. Unstarted and finished shows open at the top of page 1.
+    async fn open_next_unwatched(&mut self, terminal: &mut Tui, anime: Anime) {
+        let watched = self.watched.get(&anime.session).cloned().unwrap_or_default();
+        let after = self.history.iter()
+            .filter(|h| h.anime.session == anime.session)
+            .filter_map(|h| h.last_episode.trim().parse::<f64>().ok())
+            .fold(0.0, f64::max);
+        let finished = anime.episodes.is_some_and(|total| total > 0 && watched.len() as u32 >= total);
         self.selected_anime = Some(anime.clone());
+        if (watched.is_empty() && after == 0.0) || finished {
+            self.load_episodes(terminal, 1).await;
+            return;
+        }
 
-        match self.client.get_stream(&series_session, &ep_session).await {
-            Ok(streams) => {
+        self.is_loading = true;
+        self.set_status(format!("Looking for the next unwatched episode of {}...", anime.title));
+        let first = match self.with_loading(terminal, self.client.get_episodes(&anime.session, 1)).await {
+            Ok(first) => first,
+            Err(e) => {
                 self.is_loading = false;
-                if streams.is_empty() {
-                    self.status_message = "No streams found.".to_string();
-                    return Ok(());
-                }
-                
-                self.available_streams = streams;
-                self.quality_list_state.select(Some(0));
-                self.temp_play_data = Some((anime, ep_session, ep_num));
-                self.previous_screen = Some(self.current_screen.clone());
-                self.current_screen = CurrentScreen::QualitySelection;
-                self.status_message = "Select video quality. Enter to play, Esc to go back.".to_string();
+                self.set_status(format!("{}", e));
+                return;
             }
-            Err(e) => {
-                 self.is_loading = false;
-                 self.status_message = format!("Error fetching stream: {}", e);
+        };
+        let mut page = page_for_episode(&first, after.floor() as u32 + 1);
+        loop {
+            self.load_episodes(terminal, page).await;
+            if self.ep_page != page || self.current_screen != CurrentScreen::EpisodeList {
+                return;
             }
+            if let Some(i) = first_unwatched(&self.episode_list, &watched, after) {
+                self.episode_list_state.select(Some(i));
+                let ep = self.episode_list[i].episode.clone();
+                self.set_status(format!("Next unwatched: Ep {} (page {}/{})", ep, self.ep_page, self.ep_total_pages));
+                return;
+            }
+            if page >= self.ep_total_pages {
+                break;
+            }
+            page += 1;
         }
-        Ok(())
+        // Everything out so far is watched; the next episode isn't available yet.
+        self.episode_list_state.select(self.episode_list.len().checked_sub(1));
+        self.set_status("Caught up: no unwatched episodes are out yet".to_string());
     }
 
-    async fn play_selected_stream(&mut self, terminal: &mut Terminal<CrosstermBackend<Stdout>>) -> Result<()> {
-        let Some(idx) = self.quality_list_state.selected() else { return Ok(()) };
-        let Some((anime, ep_session, ep_num)) = self.temp_play_data.take() else { return Ok(()) };
-        let Some(link_item) = self.available_streams.get(idx) else {
-            self.temp_play_data = Some((anime, ep_session, ep_num));
+    /// The episode after the one last watched for `item`, if the backend has it yet.
+    async fn next_episode(&self, item: &HistoryItem) -> Option<Episode> {
+        let last = item.last_episode.parse::<f64>().ok()?;
+        self.find_episode(&item.anime.session, last.floor() as u32 + 1).await.ok()
+    }
+
+    /// The episode to continue `item` with, as `(episode session, episode number)`.
+    async fn resume_target(&self, item: &HistoryItem) -> (String, String) {
+        // A half-watched episode is picked up where it stopped; otherwise move on.
+        let next = match item.position_secs {
+            Some(_) => None,
+            None => self.next_episode(item).await,
+        };
+        match next {
+            Some(ep) => (ep.session, ep.episode),
+            None => (item.episode_session.clone(), item.last_episode.clone()),
+        }
+    }
+
+    /// Opens the quality menu for whatever comes next in the most recent history entry.
+    async fn continue_watching(&mut self, terminal: &mut Tui) -> Result<()> {
+        let Some(item) = self.history.first().cloned() else {
+            self.set_status("No recent activity".to_string());
             return Ok(());
         };
+        self.is_loading = true;
+        self.set_status(format!("Finding where you left off in {}...", item.anime.title));
+        let (ep_session, ep_num) = self.with_loading(terminal, self.resume_target(&item)).await;
+        self.prepare_stream_selection(terminal, item.anime, ep_session, ep_num, false).await
+    }
 
-        let link = link_item.link.clone();
-        let quality_name = link_item.name.clone();
+    /// Plays the next episode of the most recent history entry without the TUI.
+    async fn resume_headless(&mut self) -> Result<()> {
+        let item = self.history.first().cloned()
+            .context("No watch history yet, nothing to resume")?;
+        let (ep_session, ep_num) = self.resume_target(&item).await;
 
-        self.is_loading = true;
-        self.status_message = format!("Extracting stream URL ({})...", quality_name);
+        let title = item.anime.title.clone();
+        println!("Resuming {} - Ep {}...", title, ep_num);
+        let streams = self.client.get_stream(&item.anime.session, &ep_session).await?;
+        let stream = best_stream(&streams).context("No streams found")?;
+        println!("Extracting stream URL ({})...", stream.name);
+        let direct_url = self.client.extract_stream_url(&stream.link).await?;
 
-        match self.client.extract_stream_url(&link).await {
-            Ok(direct_url) => {
-                self.is_loading = false;
-                let title = anime.title.clone();
-                self.record_history(anime, ep_session, ep_num.clone());
-                self.launch_mpv(terminal, &direct_url, &title, &ep_num).await?;
-                if let Some(prev) = self.previous_screen.take() {
-                    self.current_screen = prev;
-                }
-            }
-            Err(e) => {
-                self.is_loading = false;
-                self.temp_play_data = Some((anime, ep_session, ep_num));
-                self.status_message = format!("Failed to extract stream: {}", e);
-            }
+        let start = self.saved_position(&ep_session);
+        let watch_later = watch_later_dir(&ep_session);
+        let mut command = player_command(&self.config, &direct_url, &title, Some(&item.anime.session), &ep_num, start, &watch_later)
+            .context("Invalid player settings in config.toml")?;
+        let record_on_finish = self.config.history_on == HistoryOn::Finish;
+        if !record_on_finish {
+            self.record_history(item.anime.clone(), ep_session.clone(), ep_num.clone());
+        }
+        let player = self.config.player().to_string();
+        let started = std::time::Instant::now();
+        let status = command.status().await
+            .with_context(|| format!("Failed to launch {}. Is it installed?", player))?;
+        let saved = take_saved_playback(&watch_later);
+        let watched = counts_as_watched(&saved, status.success(), started.elapsed(), self.config.watched_fraction());
+        if watched {
+            self.mark_watched(&item.anime.session, [ep_session.clone()]);
+        }
+        if record_on_finish && watched {
+            self.record_history(item.anime, ep_session.clone(), ep_num.clone());
+        }
+        self.set_position(&ep_session, saved.position_secs);
+        if let Some(hook) = self.config.post_play_command.as_deref().filter(|c| !c.trim().is_empty()) {
+            spawn_post_play_hook(hook, &title, &ep_num)?;
+        }
+        if !status.success() {
+            anyhow::bail!("{} exited with status: {}", player, status);
         }
         Ok(())
     }
 
-    async fn launch_mpv(&mut self, terminal: &mut Terminal<CrosstermBackend<Stdout>>, url: &str, title: &str, ep: &str) -> Result<()> {
-        execute!(terminal.backend_mut(), LeaveAlternateScreen, DisableMouseCapture)?;
-        disable_raw_mode()?;
-        terminal.show_cursor()?;
-
-        match Command::new("mpv")
-            .arg("--referrer=https://kwik.cx/")
-            .arg(format!("--title=Enuma - {} - Ep {}", title, ep))
-            .arg(url)
-            .status()
-            .await
-        {
-            Ok(status) => {
-                if status.success() {
-                    self.status_message = format!("Finished playing Ep {}.", ep);
-                } else {
-                    self.status_message = format!("mpv exited with status: {}", status);
-                }
-            },
-            Err(e) => {
-                self.status_message = format!("Failed to launch mpv: {}. Is it installed?", e);
-            }
-        }
+    /// Locates an episode by number, guessing its page from the first page's numbering.
+    async fn find_episode(&self, session: &str, number: u32) -> Result<Episode> {
+        let first = self.client.get_episodes(session, 1).await?;
+        let page = page_for_episode(&first, number);
 
-        enable_raw_mode()?;
-        execute!(terminal.backend_mut(), EnterAlternateScreen, EnableMouseCapture)?;
-        terminal.hide_cursor()?;
-        terminal.clear()?;
-        Ok(())
+        let res = if page == 1 { first } else { self.client.get_episodes(session, page).await? };
+        res.episodes.into_iter()
+            .find(|e| e.episode.parse::<f64>().ok() == Some(number as f64))
+            .with_context(|| format!("Episode {} not found", number))
     }
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    // Setup terminal
-    enable_raw_mode()?;
-    let mut stdout = io::stdout();
-    execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
-    let backend = CrosstermBackend::new(stdout);
-    let mut terminal = Terminal::new(backend)?;
-
-    // Create app
-    let app = App::new()?;
-    let res = run_app(&mut terminal, app).await;
-
-    // Restore terminal
-    disable_raw_mode()?;
-    execute!(
-        terminal.backend_mut(),
-        LeaveAlternateScreen,
-        DisableMouseCapture
-    )?;
-    terminal.show_cursor()?;
+    if std::env::args().skip(1).any(|a| a == "--resume") {
+        let mut app = App::new()?;
+        if let Err(e) = app.resume_headless().await {
+            eprintln!("enuma: {:#}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    let res = {
+        let _guard = TerminalGuard::enter()?;
+        let mut terminal = Terminal::new(CrosstermBackend::new(io::stdout()))?;
+
+        let mut app = App::new()?;
+        app.start_control_socket().await;
+        app.detect_graphics();
+        app.load_landing();
+        app.check_tools();
+        app.watch_interrupts();
+        let res = run_app(&mut terminal, &mut app).await;
+        let _ = App::save_data("state.json", &app.persisted_state());
+        res
+    };
 
     if let Err(err) = res {
         println!("{:?}", err);
@@ -406,162 +3645,432 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
-async fn run_app(terminal: &mut Terminal<CrosstermBackend<Stdout>>, mut app: App) -> Result<()> {
+async fn run_app(terminal: &mut Tui, app: &mut App) -> Result<()> {
     let tick_rate = std::time::Duration::from_millis(100);
     loop {
-        terminal.draw(|f| ui(f, &mut app))?;
+        let idle = app.is_idle();
+        if app.interrupted.load(Ordering::SeqCst) || (idle && app.config.idle_action == IdleAction::Exit) {
+            return Ok(());
+        }
+        // Progress only describes the loading screen, so it can't outlive it.
+        if !app.is_loading {
+            app.progress = None;
+        }
+        terminal.draw(|f| {
+            ui(f, app);
+            if idle {
+                let area = f.area();
+                f.buffer_mut().set_style(area, Style::default().fg(Color::DarkGray).bg(Color::Reset).add_modifier(Modifier::DIM));
+            }
+        })?;
+
+        if let Some(req) = app.control_rx.as_mut().and_then(|rx| rx.try_recv().ok()) {
+            let quit = matches!(req.command, ControlCommand::Quit);
+            let response = app.handle_control(terminal, req.command).await;
+            let _ = req.reply.send(response);
+            app.last_input = Instant::now();
+            if quit { return Ok(()); }
+            continue;
+        }
+
+        app.request_thumbnail();
+        app.request_metadata();
+        app.poll_downloads();
+        app.poll_episode_pages();
+        app.poll_landing();
+        app.poll_tools();
+        app.poll_instant_search();
 
         if crossterm::event::poll(tick_rate)? {
             if let Event::Key(key) = event::read()? {
-                if app.is_searching {
-                    match key.code {
-                        KeyCode::Enter => { app.perform_search().await; }
-                        KeyCode::Esc => { app.is_searching = false; }
-                        KeyCode::Backspace => { app.search_query.pop(); }
-                        KeyCode::Char(c) => { app.search_query.push(c); }
-                        _ => {}
+                app.last_input = Instant::now();
+                // Raw mode turns Ctrl+C into a key press rather than SIGINT.
+                if key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL) {
+                    return Ok(());
+                }
+                if idle { continue; }
+                if app.error_popup.take().is_some() {
+                    // The popup's own "press 'r' to retry" works straight away.
+                    if key.code == KeyCode::Char('r') && app.last_action.is_some() {
+                        app.retry_last(terminal).await?;
+                    }
+                    continue;
+                }
+                if let Some(confirm) = app.confirm.take() {
+                    app.answer_confirm(confirm, key.code == KeyCode::Char('y'));
+                    continue;
+                }
+                if let Some(overlay) = app.overlay {
+                    if key.code == KeyCode::Esc || key.code == KeyCode::Char(overlay.key()) {
+                        app.overlay = None;
                     }
                     continue;
                 }
 
-                match app.current_screen {
-                    CurrentScreen::Search => match key.code {
-                        KeyCode::Char('/') => {
-                            app.is_searching = true;
-                            app.search_query.clear();
+                match app.focus {
+                    Focus::SearchBox => {
+                        match key.code {
+                            KeyCode::Enter => { app.perform_search(terminal).await; }
+                            KeyCode::Tab => { app.search_mode = app.search_mode.toggled(); }
+                            KeyCode::Esc => {
+                                app.focus = Focus::Content;
+                                app.search_recall = None;
+                                app.cancel_instant_search();
+                            }
+                            KeyCode::Up => app.recall_search(true),
+                            KeyCode::Down => app.recall_search(false),
+                            KeyCode::Backspace => {
+                                app.search_recall = None;
+                                app.search_query.pop();
+                                app.search_edited();
+                            }
+                            KeyCode::Char(c) => {
+                                app.search_recall = None;
+                                app.search_query.push(c);
+                                app.search_edited();
+                            }
+                            _ => {}
+                        }
+                        continue;
+                    }
+                    Focus::Prompt(prompt) => {
+                        match key.code {
+                            KeyCode::Char('g') if prompt == InputPrompt::GoToEpisode && app.input_buffer.is_empty() => {
+                                app.focus = Focus::Content;
+                                app.jump_selection(true);
+                            }
+                            KeyCode::Enter => { app.submit_prompt(terminal, prompt).await; }
+                            KeyCode::Esc => {
+                                app.focus = Focus::Content;
+                                app.input_buffer.clear();
+                            }
+                            KeyCode::Backspace => { app.input_buffer.pop(); }
+                            KeyCode::Char(c) => { app.input_buffer.push(c); }
+                            _ => {}
+                        }
+                        continue;
+                    }
+                    Focus::Filter => {
+                        match key.code {
+                            KeyCode::Enter => { app.focus = Focus::Content; }
+                            KeyCode::Esc => {
+                                app.clear_filter();
+                                app.focus = Focus::Content;
+                            }
+                            KeyCode::Backspace => {
+                                app.list_filter.pop();
+                                app.refilter();
+                            }
+                            KeyCode::Char(c) => {
+                                app.list_filter.push(c);
+                                app.refilter();
+                            }
+                            KeyCode::Up | KeyCode::Down => {
+                                let len = app.list_view(app.current_screen.clone()).len();
+                                if let Some(state) = app.list_state_mut() {
+                                    cycle_selection(state, len, key.code == KeyCode::Up);
+                                }
+                            }
+                            _ => {}
                         }
+                        continue;
+                    }
+                    Focus::Content => {}
+                }
+
+                if key.code == KeyCode::Char('r') && app.last_action.is_some() {
+                    app.retry_last(terminal).await?;
+                    continue;
+                }
+
+                if key.code == KeyCode::Char('/') {
+                    if matches!(app.current_screen, CurrentScreen::Library | CurrentScreen::History) {
+                        app.focus = Focus::Filter;
+                    } else {
+                        app.focus_search();
+                    }
+                    continue;
+                }
+
+                if key.code == KeyCode::Char('?') {
+                    app.overlay = Some(Overlay::Help);
+                    continue;
+                }
+
+                if key.code == KeyCode::Char('L') {
+                    app.overlay = Some(Overlay::StatusLog);
+                    continue;
+                }
+
+                if key.code == KeyCode::Char('D') {
+                    if app.config.debug {
+                        app.show_ids = !app.show_ids;
+                        app.set_status(if app.show_ids { "Showing session ids" } else { "Hiding session ids" }.to_string());
+                    } else {
+                        app.set_status("Set debug = true in config.toml to show session ids".to_string());
+                    }
+                    continue;
+                }
+
+                // Vim-style motions on list screens. On the episode list 'g' opens go-to-episode,
+                // and a second 'g' in that prompt jumps to the top instead.
+                let code = if app.list_len().is_some() { vim_alias(key.code) } else { key.code };
+                let jump = match code {
+                    KeyCode::Char('g') if app.current_screen != CurrentScreen::EpisodeList => Some(true),
+                    KeyCode::Char('G') => Some(false),
+                    _ => None,
+                };
+                if let Some(top) = jump.filter(|_| app.list_len().is_some()) {
+                    app.jump_selection(top);
+                    continue;
+                }
+
+                match app.current_screen {
+                    CurrentScreen::Search => match code {
                         KeyCode::Char('l') => {
-                            app.current_screen = CurrentScreen::Library;
+                            app.navigate(CurrentScreen::Library);
                             app.library_list_state.select(Some(0));
                         }
                         KeyCode::Char('h') => {
-                            app.current_screen = CurrentScreen::History;
+                            app.navigate(CurrentScreen::History);
                             app.history_list_state.select(Some(0));
                         }
+                        KeyCode::Char('w') => {
+                            app.navigate(CurrentScreen::Queue);
+                            app.queue_list_state.select(Some(0));
+                        }
+                        KeyCode::Char('c') => { app.continue_watching(terminal).await?; }
+                        KeyCode::Char('u') => { app.open_prompt(InputPrompt::OpenUrl, String::new()); }
+                        KeyCode::Up => cycle_selection(&mut app.landing_list_state, app.landing.len(), true),
+                        KeyCode::Down => cycle_selection(&mut app.landing_list_state, app.landing.len(), false),
+                        KeyCode::Enter => {
+                            if let Some(anime) = app.landing_list_state.selected().and_then(|i| app.landing.get(i)).cloned() {
+                                app.selected_anime = Some(anime);
+                                app.load_episodes(terminal, 1).await;
+                            }
+                        }
+                        KeyCode::Char('s') => {
+                            app.navigate(CurrentScreen::Settings);
+                            app.settings_list_state.select(Some(0));
+                            app.set_status("Enter to change a setting; changes are saved to config.toml.".to_string());
+                        }
                         KeyCode::Esc => return Ok(()),
                         _ => {}
                     },
-                CurrentScreen::SearchResults => match key.code {
+                CurrentScreen::SearchResults => match code {
+                    KeyCode::Tab => {
+                        app.search_mode = app.search_mode.toggled();
+                        app.perform_search(terminal).await;
+                    }
                     KeyCode::Up => cycle_selection(&mut app.search_list_state, app.search_results.len(), true),
                     KeyCode::Down => cycle_selection(&mut app.search_list_state, app.search_results.len(), false),
-                    KeyCode::Char('f') => { app.toggle_library(); }
-                    KeyCode::Char('/') => { 
-                        app.is_searching = true; 
-                        app.search_query.clear();
+                    KeyCode::Left if app.search_page > 1 => {
+                        app.fetch_search_page(terminal, app.search_page - 1).await;
+                    }
+                    KeyCode::Right if app.search_page < app.search_total_pages => {
+                        app.fetch_search_page(terminal, app.search_page + 1).await;
                     }
+                    KeyCode::Char('f') => { app.toggle_library(); }
+                    KeyCode::Char(' ') => app.toggle_marked(),
+                    KeyCode::Char('i') => { app.show_info(terminal).await; }
                     KeyCode::Char('l') => {
-                        app.current_screen = CurrentScreen::Library;
+                        app.navigate(CurrentScreen::Library);
                         app.library_list_state.select(Some(0));
                     }
                     KeyCode::Char('h') => {
-                        app.current_screen = CurrentScreen::History;
+                        app.navigate(CurrentScreen::History);
                         app.history_list_state.select(Some(0));
                     }
+                    KeyCode::Char('w') => {
+                        app.navigate(CurrentScreen::Queue);
+                        app.queue_list_state.select(Some(0));
+                    }
+                    KeyCode::Char('S') => app.cycle_sort(),
+                    KeyCode::Char('R') => app.pick_random(),
                     KeyCode::Enter => {
-                        if let Some(i) = app.search_list_state.selected() {
+                        if let Some(i) = app.search_index() {
                             if let Some(anime) = app.search_results.get(i).cloned() {
                                 app.selected_anime = Some(anime);
-                                app.load_episodes(1).await;
+                                app.load_episodes(terminal, 1).await;
                             }
                         }
                     }
-                    KeyCode::Esc => {
-                        app.current_screen = CurrentScreen::Search;
-                    }
+                    KeyCode::Esc => { app.go_back(); }
                     _ => {}
                 },
-                CurrentScreen::Library => match key.code {
-                    KeyCode::Up => cycle_selection(&mut app.library_list_state, app.library.len(), true),
-                    KeyCode::Down => cycle_selection(&mut app.library_list_state, app.library.len(), false),
-                    KeyCode::Char('f') => { app.toggle_library(); }
-                    KeyCode::Char('/') => { 
-                        app.is_searching = true;
-                        app.search_query.clear();
+                CurrentScreen::Library => match code {
+                    KeyCode::Char('a') => { app.open_prompt(InputPrompt::AddToLibrary, String::new()); }
+                    KeyCode::Char('S') => app.cycle_sort(),
+                    KeyCode::Char('R') => app.pick_random(),
+                    KeyCode::Char('E') if !app.library.is_empty() => {
+                        let default = dirs::home_dir().unwrap_or_default().join("animelist.xml");
+                        app.open_prompt(InputPrompt::ExportMal, default.display().to_string());
+                    }
+                    KeyCode::Char('s') => {
+                        if let Err(e) = app.sync_anilist(terminal).await {
+                            app.is_loading = false;
+                            app.set_status(format!("AniList sync failed: {}", e));
+                        }
+                    }
+                    KeyCode::Up => {
+                        let len = app.list_view(CurrentScreen::Library).len();
+                        cycle_selection(&mut app.library_list_state, len, true);
+                    }
+                    KeyCode::Down => {
+                        let len = app.list_view(CurrentScreen::Library).len();
+                        cycle_selection(&mut app.library_list_state, len, false);
                     }
+                    KeyCode::Char('f') => { app.toggle_library(); }
+                    KeyCode::Char('i') => { app.show_info(terminal).await; }
                     KeyCode::Char('h') => {
-                        app.current_screen = CurrentScreen::History;
+                        app.navigate(CurrentScreen::History);
                         app.history_list_state.select(Some(0));
                     }
+                    KeyCode::Char('w') => {
+                        app.navigate(CurrentScreen::Queue);
+                        app.queue_list_state.select(Some(0));
+                    }
                     KeyCode::Enter => {
-                        if let Some(i) = app.library_list_state.selected() {
+                        if let Some(i) = app.library_index() {
                             if let Some(anime) = app.library.get(i).cloned() {
-                                app.selected_anime = Some(anime);
-                                app.load_episodes(1).await;
+                                app.open_next_unwatched(terminal, anime).await;
                             }
                         }
                     }
-                    KeyCode::Esc => { app.current_screen = CurrentScreen::Search; }
+                    KeyCode::Esc if !app.list_filter.is_empty() => { app.clear_filter(); }
+                    KeyCode::Esc => { app.go_back(); }
                     _ => {}
                 },
-                CurrentScreen::History => match key.code {
-                    KeyCode::Up => cycle_selection(&mut app.history_list_state, app.history.len(), true),
-                    KeyCode::Down => cycle_selection(&mut app.history_list_state, app.history.len(), false),
-                    KeyCode::Char('f') => { app.toggle_library(); }
-                    KeyCode::Char('/') => { 
-                        app.is_searching = true;
-                        app.search_query.clear();
+                CurrentScreen::History => match code {
+                    KeyCode::Char('v') => {
+                        app.history_grouped = !app.history_grouped;
+                        app.set_status(if app.history_grouped { "History grouped by date" } else { "History shown as a flat list" }.to_string());
+                    }
+                    KeyCode::Char('C') if !app.history.is_empty() => {
+                        app.open_prompt(InputPrompt::ClearHistory, String::new());
+                    }
+                    KeyCode::Char('t') => {
+                        app.history_relative = !app.history_relative;
+                        app.set_status(if app.history_relative { "Showing how long ago" } else { "Showing watch dates" }.to_string());
+                    }
+                    KeyCode::Up => {
+                        let len = app.list_view(CurrentScreen::History).len();
+                        cycle_selection(&mut app.history_list_state, len, true);
+                    }
+                    KeyCode::Down => {
+                        let len = app.list_view(CurrentScreen::History).len();
+                        cycle_selection(&mut app.history_list_state, len, false);
                     }
+                    KeyCode::Char('f') => { app.toggle_library(); }
+                    KeyCode::Char('i') => { app.show_info(terminal).await; }
                     KeyCode::Char('l') => {
-                        app.current_screen = CurrentScreen::Library;
+                        app.navigate(CurrentScreen::Library);
                         app.library_list_state.select(Some(0));
                     }
+                    KeyCode::Char('w') => {
+                        app.navigate(CurrentScreen::Queue);
+                        app.queue_list_state.select(Some(0));
+                    }
                     KeyCode::Char('e') => {
-                        if let Some(i) = app.history_list_state.selected() {
+                        if let Some(i) = app.history_index() {
                             if let Some(item) = app.history.get(i).cloned() {
                                 app.selected_anime = Some(item.anime);
-                                app.load_episodes(1).await;
+                                app.load_episodes(terminal, 1).await;
                             }
                         }
                     }
                     KeyCode::Enter => {
-                        if let Some(i) = app.history_list_state.selected() {
+                        if let Some(i) = app.history_index() {
                             if let Some(item) = app.history.get(i).cloned() {
-                                app.prepare_stream_selection(item.anime, item.episode_session, item.last_episode).await?;
+                                app.prepare_stream_selection(terminal, item.anime, item.episode_session, item.last_episode, false).await?;
                             }
                         }
                     }
-                    KeyCode::Esc => { app.current_screen = CurrentScreen::Search; }
+                    KeyCode::Esc if !app.list_filter.is_empty() => { app.clear_filter(); }
+                    KeyCode::Esc => { app.go_back(); }
                     _ => {}
                 },
-                CurrentScreen::EpisodeList => match key.code {
+                CurrentScreen::EpisodeList => match code {
                     KeyCode::Up => cycle_selection(&mut app.episode_list_state, app.episode_list.len(), true),
                     KeyCode::Down => cycle_selection(&mut app.episode_list_state, app.episode_list.len(), false),
-                    KeyCode::Left => {
-                        if app.ep_page > 1 {
-                            app.load_episodes(app.ep_page - 1).await;
-                        }
+                    KeyCode::Left if app.ep_page > 1 => {
+                        app.load_episodes(terminal, app.ep_page - 1).await;
                     }
-                    KeyCode::Right => {
-                        if app.ep_page < app.ep_total_pages {
-                            app.load_episodes(app.ep_page + 1).await;
-                        }
+                    KeyCode::Right if app.ep_page < app.ep_total_pages => {
+                        app.load_episodes(terminal, app.ep_page + 1).await;
                     }
-                    KeyCode::Char('/') => { 
-                        app.is_searching = true;
-                        app.search_query.clear();
+                    KeyCode::Char('a') => { app.enqueue_selected(); }
+                    KeyCode::Char('A') => {
+                        let start = app.episode_list_state.selected()
+                            .and_then(|i| app.episode_list.get(i))
+                            .map(|ep| ep.episode.clone())
+                            .unwrap_or_default();
+                        app.open_prompt(InputPrompt::QueueFrom, start);
                     }
-                    KeyCode::Enter => {
-                        app.play_episode().await?;
+                    KeyCode::Char('q') => {
+                        app.play_episode(terminal, true).await?;
                     }
-                    KeyCode::Esc => {
-                        app.current_screen = match () {
-                            _ if !app.search_results.is_empty() => CurrentScreen::SearchResults,
-                            _ if !app.library.is_empty() => CurrentScreen::Library,
-                            _ => CurrentScreen::Search,
-                        };
+                    KeyCode::Char('d') => { app.download_episode(terminal).await; }
+                    KeyCode::Char('B') => app.start_batch_download(),
+                    KeyCode::Char('b') => { app.open_prompt(InputPrompt::DownloadRange, String::new()); }
+                    KeyCode::Char('g') => { app.open_prompt(InputPrompt::GoToEpisode, String::new()); }
+                    KeyCode::Char('m') => { app.toggle_watched(); }
+                    KeyCode::Char('M') => { app.mark_watched_up_to_selected(); }
+                    KeyCode::Char('H') => app.toggle_recaps(),
+                    KeyCode::Char('O') => app.toggle_offline(),
+                    KeyCode::Enter => {
+                        app.play_episode(terminal, false).await?;
                     }
+                    KeyCode::Esc => { app.go_back(); }
                     _ => {}
                 }
-                CurrentScreen::QualitySelection => match key.code {
+                CurrentScreen::Info => match code {
+                    KeyCode::Up | KeyCode::Char('k') => app.info_scroll = app.info_scroll.saturating_sub(1),
+                    KeyCode::Down | KeyCode::Char('j') => app.info_scroll = app.info_scroll.saturating_add(1),
+                    KeyCode::PageUp => app.info_scroll = app.info_scroll.saturating_sub(10),
+                    KeyCode::PageDown => app.info_scroll = app.info_scroll.saturating_add(10),
+                    KeyCode::Esc => { app.go_back(); }
+                    _ => {}
+                },
+                CurrentScreen::Settings => match code {
+                    KeyCode::Up => cycle_selection(&mut app.settings_list_state, SETTINGS.len(), true),
+                    KeyCode::Down => cycle_selection(&mut app.settings_list_state, SETTINGS.len(), false),
+                    KeyCode::Enter => app.change_setting(),
+                    KeyCode::Esc => { app.go_back(); }
+                    _ => {}
+                },
+                CurrentScreen::Queue => match code {
+                    KeyCode::Up => cycle_selection(&mut app.queue_list_state, app.queue.len(), true),
+                    KeyCode::Down => cycle_selection(&mut app.queue_list_state, app.queue.len(), false),
+                    KeyCode::Char('x') => {
+                        if let Some(i) = app.queue_list_state.selected().filter(|&i| i < app.queue.len()) {
+                            let item = app.queue.remove(i);
+                            app.set_status(format!("Removed '{}' Ep {} from queue", item.anime.title, item.episode));
+                            if i >= app.queue.len() {
+                                app.queue_list_state.select(app.queue.len().checked_sub(1));
+                            }
+                            let _ = App::save_data("queue.json", &app.queue);
+                        }
+                    }
+                    KeyCode::Enter => {
+                        app.play_from_queue(terminal).await?;
+                    }
+                    KeyCode::Esc => { app.go_back(); }
+                    _ => {}
+                },
+                CurrentScreen::QualitySelection => match code {
                     KeyCode::Up => cycle_selection(&mut app.quality_list_state, app.available_streams.len(), true),
                     KeyCode::Down => cycle_selection(&mut app.quality_list_state, app.available_streams.len(), false),
+                    KeyCode::Char('t') => { app.open_prompt(InputPrompt::StartAt, String::new()); }
+                    KeyCode::Char('s') => { app.export_strm(terminal).await; }
+                    KeyCode::Char('d') => { app.download_selected_stream(terminal).await; }
+                    KeyCode::Char('y') => { app.copy_stream_url(terminal).await; }
+                    KeyCode::Char('o') => app.open_stream_in_browser(),
+                    KeyCode::Char('n') => { app.try_next_server(terminal).await?; }
                     KeyCode::Enter => {
                         app.play_selected_stream(terminal).await?;
                     }
-                    KeyCode::Esc => {
-                        app.current_screen = app.previous_screen.take()
-                            .unwrap_or(CurrentScreen::EpisodeList);
-                    }
+                    KeyCode::Esc => { app.go_back(); }
                     _ => {}
                 }
             }
@@ -573,8 +4082,8 @@ async fn run_app(terminal: &mut Terminal<CrosstermBackend<Stdout>>, mut app: App
     }
 }
 
-fn ui(f: &mut Frame, app: &mut App) {
-    let chunks = Layout::default()
+fn screen_layout(area: Rect) -> std::rc::Rc<[Rect]> {
+    Layout::default()
         .direction(Direction::Vertical)
         .constraints(
             [
@@ -583,76 +4092,325 @@ fn ui(f: &mut Frame, app: &mut App) {
                 Constraint::Length(1), // Status bar
             ]
         )
-        .split(f.area());
+        .split(area)
+}
 
-    // Search Box
-    let search_block = Paragraph::new(format!("Search: {}", app.search_query))
+/// Frame drawn while a request is in flight; only needs shared access to the app.
+fn draw_loading(f: &mut Frame, app: &App, tick: u32, elapsed: Duration) {
+    let chunks = screen_layout(f.area());
+    render_search_box(f, chunks[0], app);
+    let hud = app.config.debug.then(|| {
+        let url = app.client.last_request().unwrap_or_else(|| "-".to_string());
+        format!("{:.1}s  {}", elapsed.as_secs_f64(), url)
+    });
+    render_loading_animation(f, chunks[1], tick, app.progress, hud.as_deref());
+    render_status_bar(f, chunks[2], app);
+}
+
+fn render_search_box(f: &mut Frame, area: Rect, app: &App) {
+    let editing = app.focus == Focus::SearchBox;
+    let cursor = if editing { "_" } else { "" };
+    let search_block = Paragraph::new(format!("Search: {}{}", app.search_query, cursor))
         .block(Block::default()
             .borders(Borders::ALL)
-            .title(if app.is_searching { " Search [EDITING] " } else { " Enuma Search " })
-            .border_style(Style::default().fg(if app.is_searching { Color::Yellow } else if app.current_screen == CurrentScreen::Search { Color::Cyan } else { Color::White })));
-    f.render_widget(search_block, chunks[0]);
+            .title(if editing {
+                format!(" Search [EDITING] [{}] ", app.search_mode.label())
+            } else {
+                format!(" Enuma Search [{}] ", app.search_mode.label())
+            })
+            .border_style(Style::default().fg(app.focus.accent(Focus::SearchBox))));
+    f.render_widget(search_block, area);
+}
+
+fn render_status_bar(f: &mut Frame, area: Rect, app: &App) {
+    let status = match app.focus {
+        Focus::Prompt(prompt) => Paragraph::new(format!(" {}: {}_", prompt.label(), app.input_buffer))
+            .style(Style::default().fg(Color::Black).bg(Color::Yellow)),
+        _ => {
+            let mut text = format!(" {}", app.status_message);
+            for (path, transfer) in &app.downloads {
+                let name = path.file_stem().map(|n| n.to_string_lossy()).unwrap_or_default();
+                text.push_str(&format!("  │ ↓ {} {}", name, transfer.label()));
+            }
+            Paragraph::new(text).style(Style::default().fg(Color::Black).bg(Color::Cyan))
+        }
+    };
+    let Some(fraction) = download_fraction(&app.downloads, &app.batch).filter(|_| !matches!(app.focus, Focus::Prompt(_))) else {
+        f.render_widget(status, area);
+        return;
+    };
+    let split = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Min(1), Constraint::Length(24)])
+        .split(area);
+    f.render_widget(status, split[0]);
+    render_progress_line(f, split[1], fraction, app.animation_tick);
+}
+
+/// Overall progress of the running downloads: `None` when nothing is downloading, and an inner
+/// `None` while any of them hasn't reported a length yet. A batch counts its finished episodes.
+fn download_fraction(downloads: &HashMap<PathBuf, Transfer>, batch: &[BatchItem]) -> Option<Option<f64>> {
+    let active = batch.iter().find_map(|b| match &b.status {
+        BatchStatus::Active(t) => Some(t),
+        _ => None,
+    });
+    let mut parts: Vec<Option<f64>> = downloads.values().map(Transfer::fraction).collect();
+    if let Some(transfer) = active {
+        let finished = batch.iter().filter(|b| matches!(b.status, BatchStatus::Done | BatchStatus::Failed(_))).count();
+        parts.push(transfer.fraction().map(|f| (finished as f64 + f) / batch.len() as f64));
+    }
+    if parts.is_empty() {
+        return None;
+    }
+    let known: Option<Vec<f64>> = parts.into_iter().collect();
+    Some(known.map(|k| k.iter().sum::<f64>() / k.len() as f64))
+}
+
+/// A one-line gauge, or a sweeping bar when the fraction isn't known.
+fn render_progress_line(f: &mut Frame, area: Rect, fraction: Option<f64>, tick: u32) {
+    match fraction {
+        Some(ratio) => {
+            let gauge = LineGauge::default()
+                .ratio(ratio)
+                .label(format!("↓ {:>3.0}%", ratio * 100.0))
+                .filled_style(Style::default().fg(Color::Yellow))
+                .unfilled_style(Style::default().fg(Color::DarkGray))
+                .style(Style::default().fg(Color::Black).bg(Color::Cyan));
+            f.render_widget(gauge, area);
+        }
+        None => {
+            let bar = Paragraph::new(format!(" ↓ {}", indeterminate_bar(area.width.saturating_sub(4) as usize, tick)))
+                .style(Style::default().fg(Color::Black).bg(Color::Cyan));
+            f.render_widget(bar, area);
+        }
+    }
+}
+
+/// A block bouncing across a `width`-wide track, for progress that can't be measured.
+fn indeterminate_bar(width: usize, tick: u32) -> String {
+    let block = (width / 4).max(1).min(width);
+    let travel = width - block;
+    let period = (travel * 2).max(1);
+    let step = tick as usize % period;
+    let start = if step <= travel { step } else { period - step };
+    format!("{}{}{}", "░".repeat(start), "█".repeat(block), "░".repeat(travel - start))
+}
+
+fn ui(f: &mut Frame, app: &mut App) {
+    let chunks = screen_layout(f.area());
+    render_search_box(f, chunks[0], app);
 
     // Build library session set once for O(1) lookups in render
-    let lib_sessions: HashSet<&str> = app.library.iter().map(|a| a.session.as_str()).collect();
+    let ctx = ListCtx {
+        lib_sessions: app.library.iter().map(|a| a.session.as_str()).collect(),
+        title_cap: app.config.title_max_width,
+        metadata: &app.metadata,
+        accent: if app.focus == Focus::Filter { Color::Cyan } else { app.focus.accent(Focus::Content) },
+        filter: if app.focus == Focus::Filter { format!("[/{}_] ", app.list_filter) }
+            else if app.list_filter.is_empty() { String::new() }
+            else { format!("[/{}] ", app.list_filter) },
+        show_ids: app.config.debug && app.show_ids,
+        progress: watch_progress(&app.watched),
+        marked: {
+            let results = &app.search_results;
+            app.marked.iter().filter_map(|&i| results.get(i)).map(|a| a.session.as_str()).collect()
+        },
+    };
 
     // Main Content
     if app.is_loading {
-        render_loading_animation(f, chunks[1], app.animation_tick);
+        render_loading_animation(f, chunks[1], app.animation_tick, app.progress, None);
     } else {
         match app.current_screen {
             CurrentScreen::Search => {
-            let welcome = Paragraph::new("Welcome to Enuma!\n\nPress '/' to start searching.\n\nControls:\n- '/': Focus Search bar\n- Enter (while searching): Perform search\n- Esc (while searching): Cancel search\n\nNavigation:\n- 'l': View Library\n- 'h': View History\n- Esc: Exit app")
-                .block(Block::default().borders(Borders::ALL).title(" Help ").border_style(Style::default().fg(Color::Gray)))
+            let stats = dashboard(&app.library, &app.history);
+            let welcome = Paragraph::new(format!("Welcome to Enuma!\n\n{}\n\nPress '/' to start searching.\n\nControls:\n- '/': Focus Search bar (filters instead on Library and History)\n- Enter (while searching): Perform search\n- Tab: Toggle exact/fuzzy matching\n- Up/Down (while searching): Recall recent searches\n- Esc (while searching): Cancel search\n\nNavigation:\n- 'l': View Library\n- 'h': View History\n- 'w': View Watch Queue\n- 'c': Continue watching the latest show\n- 'u': Paste a kwik or animepahe URL\n- 's': Settings\n- '?': Keys for the current screen\n- Esc: Exit app", stats))
+                .block(Block::default().borders(Borders::ALL).title(" Help ").border_style(Style::default().fg(ctx.accent)))
                 .wrap(Wrap { trim: true })
                 .style(Style::default().fg(Color::White));
-            f.render_widget(welcome, chunks[1]);
+            let area = match &app.tool_warning {
+                Some(warning) => {
+                    let split = Layout::default()
+                        .direction(Direction::Vertical)
+                        .constraints([Constraint::Length(4), Constraint::Min(1)])
+                        .split(chunks[1]);
+                    let banner = Paragraph::new(warning.as_str())
+                        .block(Block::default().borders(Borders::ALL).title(" Missing tools ").border_style(Style::default().fg(Color::Red)))
+                        .wrap(Wrap { trim: true })
+                        .style(Style::default().fg(Color::Red));
+                    f.render_widget(banner, split[0]);
+                    split[1]
+                }
+                None => chunks[1],
+            };
+            if app.landing.is_empty() {
+                f.render_widget(welcome, area);
+            } else {
+                let source = match app.config.landing() {
+                    Some(Landing::Query(query)) => query,
+                    _ => "Airing now".to_string(),
+                };
+                let title = format!(" {} · Enter to open, '/' to search, '?' for keys ", source);
+                let split = Layout::default()
+                    .direction(Direction::Vertical)
+                    .constraints([Constraint::Length(1), Constraint::Min(1)])
+                    .split(area);
+                f.render_widget(Paragraph::new(format!(" {}", stats)).style(Style::default().fg(Color::Gray)), split[0]);
+                render_anime_list(f, split[1], &app.landing, &mut app.landing_list_state, &title, &ctx);
+            }
         }
         CurrentScreen::SearchResults => {
-            render_anime_list(f, chunks[1], &app.search_results, &mut app.search_list_state, &lib_sessions, " Results ");
+            let view: Vec<Anime> = app.list_view(CurrentScreen::SearchResults).into_iter().map(|i| app.search_results[i].clone()).collect();
+            let page = match app.search_total_pages {
+                0 | 1 => String::new(),
+                total => format!("- Page {}/{} ", app.search_page, total),
+            };
+            let title = format!(" Results {}{}", page, app.sort_mode.suffix());
+            render_anime_list(f, chunks[1], &view, &mut app.search_list_state, &title, &ctx);
         }
         CurrentScreen::Library => {
             if app.library.is_empty() {
-                let empty = Paragraph::new("Library is empty. Search and press 'f' to add some!")
-                    .block(Block::default().borders(Borders::ALL).title(" Library ").border_style(Style::default().fg(Color::Cyan)))
+                let empty = Paragraph::new("Library is empty. Search and press 'f' to add some, or 'a' to paste an anime URL.")
+                    .block(Block::default().borders(Borders::ALL).title(" Library ").border_style(Style::default().fg(ctx.accent)))
                     .style(Style::default().fg(Color::Yellow));
                 f.render_widget(empty, chunks[1]);
             } else {
-                render_anime_list(f, chunks[1], &app.library, &mut app.library_list_state, &lib_sessions, " Library ");
+                let view: Vec<Anime> = app.list_view(CurrentScreen::Library).into_iter().map(|i| app.library[i].clone()).collect();
+                let title = format!(" Library {}{}", app.sort_mode.suffix(), ctx.filter);
+                render_anime_list(f, chunks[1], &view, &mut app.library_list_state, &title, &ctx);
             }
         }
         CurrentScreen::History => {
             if app.history.is_empty() {
                 let empty = Paragraph::new("No watch history yet.")
-                    .block(Block::default().borders(Borders::ALL).title(" History ").border_style(Style::default().fg(Color::Cyan)))
+                    .block(Block::default().borders(Borders::ALL).title(" History ").border_style(Style::default().fg(ctx.accent)))
                     .style(Style::default().fg(Color::Yellow));
                 f.render_widget(empty, chunks[1]);
             } else {
-                render_history_list(f, chunks[1], &app.history, &mut app.history_list_state, &lib_sessions);
+                let view: Vec<HistoryItem> = app.list_view(CurrentScreen::History).into_iter().map(|i| app.history[i].clone()).collect();
+                render_history_list(f, chunks[1], &view, &mut app.history_list_state, app.history_grouped, app.history_relative, &ctx);
             }
         }
         CurrentScreen::EpisodeList => {
+            let area = if app.batch.is_empty() {
+                chunks[1]
+            } else {
+                let split = Layout::default()
+                    .direction(Direction::Vertical)
+                    .constraints([Constraint::Min(3), Constraint::Length(app.batch.len().min(8) as u16 + 2)])
+                    .split(chunks[1]);
+                render_batch(f, split[1], &app.batch);
+                split[0]
+            };
+             let anime_session = app.selected_anime.as_ref().map(|a| a.session.as_str()).unwrap_or_default();
              let items: Vec<ListItem> = app.episode_list
                 .iter()
-                .map(|ep| ListItem::new(format!(" Episode {}", ep.episode)))
+                .map(|ep| {
+                    let mark = if app.is_watched(anime_session, &ep.session) { "✓ " } else { "  " };
+                    let offline = if app.offline_episodes.contains(&ep.episode) { "  [offline]" } else { "" };
+                    if ctx.show_ids {
+                        ListItem::new(format!("{}{}{} [{}]", mark, episode_label(ep), offline, ep.session))
+                    } else {
+                        ListItem::new(format!("{}{}{}", mark, episode_label(ep), offline))
+                    }
+                })
                 .collect();
 
-            let title = format!(" Episodes - Page {}/{} ", app.ep_page, app.ep_total_pages);
+            let title = format!(" Episodes - Page {}/{}{} ", app.ep_page, app.ep_total_pages, if app.offline_only { " (downloaded)" } else { "" });
             let list = List::new(items)
-                .block(Block::default().borders(Borders::ALL).title(title).border_style(Style::default().fg(Color::Cyan)))
+                .block(Block::default().borders(Borders::ALL).title(title).border_style(Style::default().fg(ctx.accent)))
                 .highlight_style(Style::default().add_modifier(Modifier::BOLD).fg(Color::Magenta))
                 .highlight_symbol("▶ ");
-                
-            f.render_stateful_widget(list, chunks[1], &mut app.episode_list_state);
+
+            let image_preview = app.image_preview().is_some();
+            if app.config.ascii_thumbnails || image_preview {
+                let layout = Layout::default()
+                    .direction(Direction::Horizontal)
+                    .constraints([Constraint::Percentage(60), Constraint::Percentage(40)])
+                    .split(area);
+                f.render_stateful_widget(list, layout[0], &mut app.episode_list_state);
+
+                let preview_block = Block::default().borders(Borders::ALL).title(" Preview ").border_style(Style::default().fg(Color::Gray));
+                let inner = preview_block.inner(layout[1]);
+                app.thumb_size = (inner.width, inner.height);
+                let snapshot = app.selected_snapshot().unwrap_or_default().to_string();
+                let text = if image_preview {
+                    match app.images.get(&snapshot) {
+                        Some(Some(_)) => String::new(),
+                        Some(None) => "No preview available.".to_string(),
+                        None => "Loading preview...".to_string(),
+                    }
+                } else {
+                    match app.thumbnails.get(&snapshot) {
+                        Some(Some(art)) => art.clone(),
+                        Some(None) => "No preview available.".to_string(),
+                        None => "Loading preview...".to_string(),
+                    }
+                };
+                let preview = Paragraph::new(text)
+                    .block(preview_block)
+                    .style(Style::default().fg(Color::White));
+                f.render_widget(preview, layout[1]);
+                if image_preview && app.image_visible() {
+                    if let Some(Some(image)) = app.images.get_mut(&snapshot) {
+                        f.render_stateful_widget(StatefulImage::default(), inner, image);
+                    }
+                }
+            } else {
+                f.render_stateful_widget(list, area, &mut app.episode_list_state);
+            }
+        }
+        CurrentScreen::Queue => {
+            if app.queue.is_empty() {
+                let empty = Paragraph::new("Queue is empty. Press 'a' on an episode, or 'A' to queue a range.")
+                    .block(Block::default().borders(Borders::ALL).title(" Watch Queue ").border_style(Style::default().fg(ctx.accent)))
+                    .style(Style::default().fg(Color::Yellow));
+                f.render_widget(empty, chunks[1]);
+            } else {
+                let items: Vec<ListItem> = app.queue
+                    .iter()
+                    .map(|q| if ctx.show_ids {
+                        ListItem::new(format!(" {} - Ep {} [{}]", q.anime.title, q.episode, q.episode_session))
+                    } else {
+                        ListItem::new(format!(" {} - Ep {}", q.anime.title, q.episode))
+                    })
+                    .collect();
+
+                let list = List::new(items)
+                    .block(Block::default().borders(Borders::ALL).title(format!(" Watch Queue ({}) ", app.queue.len())).border_style(Style::default().fg(ctx.accent)))
+                    .highlight_style(Style::default().add_modifier(Modifier::BOLD).fg(Color::Yellow))
+                    .highlight_symbol("▶ ");
+
+                f.render_stateful_widget(list, chunks[1], &mut app.queue_list_state);
+            }
+        }
+        CurrentScreen::Info => {
+            if let Some(anime) = &app.info_anime {
+                let info = app.info.get(&anime.session).and_then(|i| i.as_ref());
+                render_info(f, chunks[1], anime, info, app.info_scroll, &ctx);
+            }
+        }
+        CurrentScreen::Settings => {
+            let items: Vec<ListItem> = SETTINGS.iter()
+                .map(|s| ListItem::new(format!(" {:<24} {}", s.label(), s.value(&app.config))))
+                .collect();
+            let list = List::new(items)
+                .block(Block::default().borders(Borders::ALL).title(" Settings ").border_style(Style::default().fg(ctx.accent)))
+                .highlight_style(Style::default().add_modifier(Modifier::BOLD).fg(Color::Yellow))
+                .highlight_symbol("▶ ");
+            f.render_stateful_widget(list, chunks[1], &mut app.settings_list_state);
         }
         CurrentScreen::QualitySelection => {
              let items: Vec<ListItem> = app.available_streams
                 .iter()
-                .map(|s| ListItem::new(format!(" {}", s.name)))
+                .map(|s| match app.failed_streams.contains(&s.link) {
+                    true => ListItem::new(format!(" ✗ {}", s.name)).style(Style::default().fg(Color::DarkGray)),
+                    false => ListItem::new(format!(" {}", s.name)),
+                })
                 .collect();
 
             let list = List::new(items)
-                .block(Block::default().borders(Borders::ALL).title(" Select Quality ").border_style(Style::default().fg(Color::Cyan)))
+                .block(Block::default().borders(Borders::ALL).title(" Select Quality ").border_style(Style::default().fg(ctx.accent)))
                 .highlight_style(Style::default().add_modifier(Modifier::BOLD).fg(Color::Yellow))
                 .highlight_symbol("▶ ");
                 
@@ -661,41 +4419,346 @@ fn ui(f: &mut Frame, app: &mut App) {
     }
 }
 
-fn render_loading_animation(f: &mut Frame, area: Rect, tick: u32) {
+    app.status_width = chunks[2].width;
+    render_status_bar(f, chunks[2], app);
+    if let Some(Confirm::RemoveFromLibrary(session)) = &app.confirm {
+        let title = app.library.iter().find(|a| &a.session == session).map(|a| a.title.as_str()).unwrap_or_default();
+        render_confirm(f, &format!("Remove '{}' from your library? y/n", title));
+    }
+    match app.overlay {
+        Some(Overlay::Help) => render_help(f, &app.current_screen),
+        Some(Overlay::StatusLog) => render_status_log(f, &app.status_log),
+        None => {}
+    }
+    if let Some(message) = &app.error_popup {
+        render_error_popup(f, message);
+    }
+}
+
+/// Keys for `screen`, followed by the ones that work everywhere.
+fn help_entries(screen: &CurrentScreen) -> Vec<(&'static str, &'static str)> {
+    let mut keys = match screen {
+        CurrentScreen::Search => vec![
+            ("/", "Focus the search bar"),
+            ("Enter", "Search (in the search bar)"),
+            ("Tab", "Toggle exact/fuzzy matching"),
+            ("Up/Down", "Recall recent searches (in the search bar)"),
+            ("l / h / w", "Library / History / Watch queue"),
+            ("c", "Continue watching the latest show"),
+            ("u", "Open a kwik or animepahe URL"),
+            ("Up/Down, Enter", "Browse and open the start screen list"),
+            ("s", "Settings"),
+            ("Esc", "Quit"),
+        ],
+        CurrentScreen::SearchResults => vec![
+            ("Enter", "Show episodes"),
+            ("Left/Right", "Previous / next page of results"),
+            ("f", "Add to / remove from library"),
+            ("Space", "Tick for a bulk 'f'"),
+            ("i", "Full details"),
+            ("S", "Cycle sort order"),
+            ("R", "Pick a random entry"),
+            ("Tab", "Toggle exact/fuzzy and search again"),
+            ("l / h / w", "Library / History / Watch queue"),
+            ("Esc", "Back"),
+        ],
+        CurrentScreen::Library => vec![
+            ("Enter", "Show episodes at the next unwatched one"),
+            ("/", "Filter the list"),
+            ("f", "Remove from library (asks first)"),
+            ("a", "Add a title by name"),
+            ("i", "Full details"),
+            ("S", "Cycle sort order"),
+            ("R", "Pick a random entry"),
+            ("s", "Import from AniList"),
+            ("E", "Export as MyAnimeList XML"),
+            ("h / w", "History / Watch queue"),
+            ("Esc", "Clear filter, then back"),
+        ],
+        CurrentScreen::History => vec![
+            ("Enter", "Resume the last episode"),
+            ("e", "Show episodes"),
+            ("/", "Filter the list"),
+            ("f", "Add to / remove from library"),
+            ("i", "Full details"),
+            ("v", "Group by date / flat list"),
+            ("t", "Relative / absolute times"),
+            ("C", "Clear history"),
+            ("l / w", "Library / Watch queue"),
+            ("Esc", "Clear filter, then back"),
+        ],
+        CurrentScreen::EpisodeList => vec![
+            ("Enter", "Play"),
+            ("q", "Pick the quality before playing"),
+            ("Left/Right", "Previous / next page"),
+            ("g", "Go to episode (gg: top)"),
+            ("a / A", "Queue episode / queue from an episode on"),
+            ("m / M", "Toggle watched / mark watched up to here"),
+            ("d", "Download"),
+            ("B", "Download the whole page"),
+            ("b", "Download episodes by number, e.g. 5-12 or 5,7,9"),
+            ("H", "Hide / show recaps and duplicates"),
+            ("O", "Show only downloaded episodes / every episode"),
+            ("Esc", "Back"),
+        ],
+        CurrentScreen::QualitySelection => vec![
+            ("Enter", "Play"),
+            ("t", "Start at a timestamp"),
+            ("d", "Download"),
+            ("y", "Copy the stream URL"),
+            ("o", "Open the kwik page in your browser"),
+            ("n", "Try the next server that hasn't failed"),
+            ("s", "Export a .strm file"),
+            ("Esc", "Back"),
+        ],
+        CurrentScreen::Queue => vec![
+            ("Enter", "Play the queue"),
+            ("x", "Remove from queue"),
+            ("Esc", "Back"),
+        ],
+        CurrentScreen::Settings => vec![
+            ("Enter", "Change setting"),
+            ("Esc", "Back"),
+        ],
+        CurrentScreen::Info => vec![
+            ("Up/Down", "Scroll"),
+            ("PgUp/PgDn", "Scroll a page"),
+            ("Esc", "Back"),
+        ],
+    };
+    if !matches!(screen, CurrentScreen::Search | CurrentScreen::Info) {
+        keys.push(("j/k, g/G", "Move, jump to top / bottom"));
+    }
+    keys.push(("r", "Retry a failed request"));
+    keys.push(("L", "Recent status messages"));
+    keys.push(("?", "Close this help"));
+    keys
+}
+
+fn render_confirm(f: &mut Frame, question: &str) {
+    let area = f.area();
+    let width = (display_width(question) as u16 + 4).min(area.width);
+    let height = 3.min(area.height);
+    let popup = Rect {
+        x: area.x + (area.width - width) / 2,
+        y: area.y + (area.height - height) / 2,
+        width,
+        height,
+    };
+    let paragraph = Paragraph::new(format!(" {}", question))
+        .block(Block::default().borders(Borders::ALL).title(" Confirm ").border_style(Style::default().fg(Color::Yellow)))
+        .style(Style::default().fg(Color::White));
+    f.render_widget(Clear, popup);
+    f.render_widget(paragraph, popup);
+}
+
+fn render_status_log(f: &mut Frame, log: &VecDeque<(String, String)>) {
+    let area = f.area();
+    let width = area.width.saturating_sub(4).min(100);
+    let height = (log.len().max(1) as u16 + 2).min(area.height);
+    let popup = Rect {
+        x: area.x + (area.width - width) / 2,
+        y: area.y + (area.height - height) / 2,
+        width,
+        height,
+    };
+    let text = if log.is_empty() {
+        "No messages yet.".to_string()
+    } else {
+        log.iter().map(|(time, message)| format!(" {}  {}", time, message)).collect::<Vec<_>>().join("\n")
+    };
+    let paragraph = Paragraph::new(text)
+        .block(Block::default().borders(Borders::ALL).title(" Recent messages (L or Esc to close) ").border_style(Style::default().fg(Color::Cyan)))
+        .style(Style::default().fg(Color::White));
+    f.render_widget(Clear, popup);
+    f.render_widget(paragraph, popup);
+}
+
+fn render_help(f: &mut Frame, screen: &CurrentScreen) {
+    let entries = help_entries(screen);
+    let key_width = entries.iter().map(|(k, _)| display_width(k)).max().unwrap_or(0);
+    let text: Vec<String> = entries.iter()
+        .map(|(key, action)| format!(" {}{}  {}", key, " ".repeat(key_width - display_width(key)), action))
+        .collect();
+
+    let area = f.area();
+    let width = (text.iter().map(|l| display_width(l)).max().unwrap_or(0) as u16 + 4).min(area.width);
+    let height = (text.len() as u16 + 2).min(area.height);
+    let popup = Rect {
+        x: area.x + (area.width - width) / 2,
+        y: area.y + (area.height - height) / 2,
+        width,
+        height,
+    };
+    let paragraph = Paragraph::new(text.join("\n"))
+        .block(Block::default().borders(Borders::ALL).title(" Keys (? or Esc to close) ").border_style(Style::default().fg(Color::Cyan)))
+        .style(Style::default().fg(Color::White));
+    f.render_widget(Clear, popup);
+    f.render_widget(paragraph, popup);
+}
+
+fn render_batch(f: &mut Frame, area: Rect, batch: &[BatchItem]) {
+    let finished = batch.iter().filter(|b| matches!(b.status, BatchStatus::Done | BatchStatus::Failed(_))).count();
+    let items: Vec<ListItem> = batch.iter()
+        .map(|b| {
+            let (text, color) = match &b.status {
+                BatchStatus::Queued => ("queued".to_string(), Color::DarkGray),
+                BatchStatus::Active(transfer) => (transfer.label(), Color::Yellow),
+                BatchStatus::Done => ("done".to_string(), Color::Green),
+                BatchStatus::Failed(e) => (format!("failed: {}", e), Color::Red),
+            };
+            ListItem::new(format!(" Ep {:<6} {}", b.ep, text)).style(Style::default().fg(color))
+        })
+        .collect();
+    // Keep the episode in progress in view once the batch outgrows the panel.
+    let mut state = ListState::default();
+    state.select(batch.iter().position(|b| matches!(b.status, BatchStatus::Active(_))));
+    let list = List::new(items)
+        .block(Block::default().borders(Borders::ALL).title(format!(" Batch download {}/{} ", finished, batch.len())).border_style(Style::default().fg(Color::Gray)));
+    f.render_stateful_widget(list, area, &mut state);
+}
+
+/// Full text of an error, centred over the screen with word wrap.
+fn render_error_popup(f: &mut Frame, message: &str) {
+    let area = f.area();
+    let width = area.width.saturating_sub(4).min(80);
+    let lines = message.chars().count() as u16 / width.saturating_sub(4).max(1) + 1;
+    let height = (lines + 4).min(area.height);
+    let popup = Rect {
+        x: area.x + (area.width - width) / 2,
+        y: area.y + (area.height - height) / 2,
+        width,
+        height,
+    };
+    let text = format!("{}\n\nPress any key to dismiss.", message);
+    let paragraph = Paragraph::new(text)
+        .block(Block::default().borders(Borders::ALL).title(" Error ").border_style(Style::default().fg(Color::Red)))
+        .wrap(Wrap { trim: true })
+        .style(Style::default().fg(Color::White));
+    f.render_widget(Clear, popup);
+    f.render_widget(paragraph, popup);
+}
+
+/// Spinner for the loading screen, with a gauge under it: filled to `progress` when the work
+/// can be counted, otherwise a bar sweeping back and forth.
+fn render_loading_animation(f: &mut Frame, area: Rect, tick: u32, progress: Option<f64>, hud: Option<&str>) {
     let frames = ["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"];
     let frame = frames[(tick as usize) % frames.len()];
     
-    let text = format!("\n\n\n  {}  LOADING...  ", frame);
+    // Leave a blank line where the gauge goes.
+    let mut text = format!("\n\n\n  {}  LOADING...  \n\n", frame);
+    if let Some(hud) = hud {
+        text.push_str(&format!("\n\n{}", hud));
+    }
     let loading = Paragraph::new(text)
         .alignment(ratatui::layout::Alignment::Center)
         .block(Block::default().borders(Borders::ALL).border_style(Style::default().fg(Color::Yellow)))
-        .style(Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD));
+        .style(Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD))
+        .wrap(Wrap { trim: false });
     
     f.render_widget(loading, area);
+
+    let width = area.width.saturating_sub(4).min(40);
+    if area.height < 8 || width < 8 {
+        return;
+    }
+    let bar = Rect { x: area.x + (area.width - width) / 2, y: area.y + 5, width, height: 1 };
+    match progress {
+        Some(ratio) => {
+            let gauge = Gauge::default()
+                .ratio(ratio.clamp(0.0, 1.0))
+                .gauge_style(Style::default().fg(Color::Yellow).bg(Color::DarkGray));
+            f.render_widget(gauge, bar);
+        }
+        None => {
+            let sweep = Paragraph::new(indeterminate_bar(width as usize, tick))
+                .style(Style::default().fg(Color::Yellow));
+            f.render_widget(sweep, bar);
+        }
+    }
+}
+
+/// How many episodes of each anime session are marked watched. History isn't counted: its
+/// last episode is a number, and later seasons often continue the numbering.
+fn watch_progress(watched: &HashMap<String, HashSet<String>>) -> HashMap<&str, u32> {
+    watched.iter()
+        .map(|(session, eps)| (session.as_str(), eps.len() as u32))
+        .collect()
 }
-    // Status Bar
-    let status = Paragraph::new(format!(" {}", app.status_message))
-        .style(Style::default().fg(Color::Black).bg(Color::Cyan));
-    f.render_widget(status, chunks[2]);
+
+/// Read-only state shared by the anime/history list renderers.
+struct ListCtx<'a> {
+    lib_sessions: HashSet<&'a str>,
+    title_cap: Option<usize>,
+    metadata: &'a HashMap<String, Option<Metadata>>,
+    /// Border colour, dimmed while the search box or a prompt has focus.
+    accent: Color,
+    /// Append raw session tokens to each row.
+    show_ids: bool,
+    /// Active Library/History filter, shown after the list title.
+    filter: String,
+    /// Episodes marked watched per anime session.
+    progress: HashMap<&'a str, u32>,
+    /// Sessions of search results ticked for a bulk library change.
+    marked: HashSet<&'a str>,
 }
 
-fn render_anime_list(f: &mut Frame, area: Rect, list_data: &[Anime], state: &mut ListState, lib_sessions: &HashSet<&str>, title: &str) {
+fn render_anime_list(f: &mut Frame, area: Rect, list_data: &[Anime], state: &mut ListState, title: &str, ctx: &ListCtx) {
     let layout = Layout::default()
         .direction(Direction::Horizontal)
         .constraints([Constraint::Percentage(60), Constraint::Percentage(40)])
         .split(area);
 
+    // Entries sharing a franchise (seasons, parts, OVAs) get a tag to tell them apart
+    let mut franchise_counts: HashMap<String, usize> = HashMap::new();
+    for anime in list_data {
+        *franchise_counts.entry(franchise_key(&anime.title)).or_default() += 1;
+    }
+
+    let max_title = title_width(layout[0], 2, ctx.title_cap);
     let items: Vec<ListItem> = list_data
         .iter()
         .map(|i| {
-            let lib_mark = if lib_sessions.contains(i.session.as_str()) { "❤ " } else { "  " };
-            let title = truncate_str(&i.title, 37);
-            ListItem::new(format!("{}{}", lib_mark, title))
+            let lib_mark = if ctx.lib_sessions.contains(i.session.as_str()) { "❤ " } else { "  " };
+            let marked = ctx.marked.contains(i.session.as_str());
+            let tick = match (ctx.marked.is_empty(), marked) {
+                (true, _) => "",
+                (false, true) => "● ",
+                (false, false) => "  ",
+            };
+            let mut tag = if franchise_counts.get(&franchise_key(&i.title)).copied().unwrap_or(0) > 1 {
+                entry_tag(i)
+            } else {
+                String::new()
+            };
+            if ctx.show_ids {
+                tag.push_str(&format!(" [{}]", i.session));
+            }
+            let score = i.score.or_else(|| ctx.metadata.get(&i.session).and_then(|m| m.as_ref()).and_then(|m| m.score));
+            let score_badge = score.map(|s| (format!(" {:.1}", s), score_color(s)));
+            let status_badge = status_badge(&i.status);
+            let badges_width = score_badge.as_ref().map_or(0, |(text, _)| display_width(text))
+                + status_badge.map_or(0, |(text, _)| display_width(text) + 1);
+            let title = truncate_str(&i.title, max_title.saturating_sub(display_width(&tag) + display_width(tick) + badges_width).max(4));
+
+            let mut spans = vec![
+                Span::styled(tick, Style::default().fg(Color::Cyan)),
+                Span::styled(lib_mark, Style::default().fg(Color::Red)),
+                Span::styled(title, if marked { Style::default().fg(Color::Cyan) } else { Style::default() }),
+                Span::styled(tag, Style::default().fg(Color::DarkGray)),
+            ];
+            if let Some((text, color)) = score_badge {
+                spans.push(Span::styled(text, Style::default().fg(color)));
+            }
+            if let Some((text, color)) = status_badge {
+                spans.push(Span::styled(format!(" {}", text), Style::default().fg(color)));
+            }
+            ListItem::new(Line::from(spans))
         })
         .collect();
 
     let list = List::new(items)
-        .block(Block::default().borders(Borders::ALL).title(title).border_style(Style::default().fg(Color::Cyan)))
+        .block(Block::default().borders(Borders::ALL).title(title).border_style(Style::default().fg(ctx.accent)))
         .highlight_style(Style::default().add_modifier(Modifier::BOLD).fg(Color::Yellow))
         .highlight_symbol("▶ ");
 
@@ -704,55 +4767,659 @@ fn render_anime_list(f: &mut Frame, area: Rect, list_data: &[Anime], state: &mut
     // Details Panel
     if let Some(i) = state.selected() {
         if let Some(anime) = list_data.get(i) {
-            render_details(f, layout[1], anime, lib_sessions);
+            render_details(f, layout[1], anime, ctx);
         }
     }
 }
 
-fn render_history_list(f: &mut Frame, area: Rect, list_data: &[HistoryItem], state: &mut ListState, lib_sessions: &HashSet<&str>) {
+/// Green for 8 and up, yellow from 6, red below.
+fn score_color(score: f64) -> Color {
+    if score >= 8.0 {
+        Color::Green
+    } else if score >= 6.0 {
+        Color::Yellow
+    } else {
+        Color::Red
+    }
+}
+
+/// Short airing-status label for list rows, or `None` for statuses we don't recognise.
+fn status_badge(status: &str) -> Option<(&'static str, Color)> {
+    let status = status.to_lowercase();
+    if status.contains("currently") || status.contains("ongoing") {
+        Some(("airing", Color::Green))
+    } else if status.contains("not yet") || status.contains("upcoming") {
+        Some(("upcoming", Color::Magenta))
+    } else if status.contains("finished") || status.contains("completed") {
+        Some(("ended", Color::DarkGray))
+    } else {
+        None
+    }
+}
+
+fn history_summary(history: &[HistoryItem]) -> String {
+    let today = chrono::Local::now().date_naive();
+    let (mut day, mut week, mut month) = (0, 0, 0);
+    for h in history {
+        let Ok(watched) = NaiveDateTime::parse_from_str(&h.last_watched, "%Y-%m-%d %H:%M") else { continue };
+        let date = watched.date();
+        if date == today { day += 1; }
+        if date.iso_week() == today.iso_week() { week += 1; }
+        if date.year() == today.year() && date.month() == today.month() { month += 1; }
+    }
+    format!(" Today: {} · This week: {} · This month: {}", day, week, month)
+}
+
+/// Date header for a history entry: "Today", "Yesterday", or the ISO date.
+fn history_date_label(last_watched: &str, today: chrono::NaiveDate) -> String {
+    let Ok(watched) = NaiveDateTime::parse_from_str(last_watched, "%Y-%m-%d %H:%M") else {
+        return "Unknown date".to_string();
+    };
+    let date = watched.date();
+    if date == today {
+        "Today".to_string()
+    } else if today.pred_opt() == Some(date) {
+        "Yesterday".to_string()
+    } else {
+        date.format("%Y-%m-%d").to_string()
+    }
+}
+
+/// "5 min ago", "3 days ago" and so on for a `last_watched` stamp; `None` if it doesn't parse.
+fn relative_time(last_watched: &str, now: NaiveDateTime) -> Option<String> {
+    let watched = NaiveDateTime::parse_from_str(last_watched, "%Y-%m-%d %H:%M").ok()?;
+    let minutes = (now - watched).num_minutes().max(0);
+    let plural = |n: i64, unit: &str| format!("{} {}{} ago", n, unit, if n == 1 { "" } else { "s" });
+    Some(match minutes {
+        0 => "just now".to_string(),
+        1..=59 => format!("{} min ago", minutes),
+        60..=1439 => plural(minutes / 60, "hour"),
+        1440..=2879 => "yesterday".to_string(),
+        2880..=10079 => plural(minutes / 1440, "day"),
+        10080..=43199 => plural(minutes / 10080, "week"),
+        43200..=525599 => plural(minutes / 43200, "month"),
+        _ => plural(minutes / 525600, "year"),
+    })
+}
+
+/// `state` always indexes into `list_data`; in grouped mode the header rows are only
+/// added for display, so navigation never lands on them.
+fn render_history_list(f: &mut Frame, area: Rect, list_data: &[HistoryItem], state: &mut ListState, grouped: bool, relative: bool, ctx: &ListCtx) {
+    let rows = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Length(1), Constraint::Min(1)])
+        .split(area);
+
+    let summary = Paragraph::new(history_summary(list_data))
+        .style(Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD));
+    f.render_widget(summary, rows[0]);
+
     let layout = Layout::default()
         .direction(Direction::Horizontal)
         .constraints([Constraint::Percentage(60), Constraint::Percentage(40)])
-        .split(area);
+        .split(rows[1]);
 
-    let items: Vec<ListItem> = list_data
-        .iter()
-        .map(|h| {
-            let lib_mark = if lib_sessions.contains(h.anime.session.as_str()) { "❤ " } else { "  " };
-            let title = truncate_str(&h.anime.title, 27);
-            ListItem::new(format!("{}{:<35} Ep {:<3} [{}]", lib_mark, title, h.last_episode, h.last_watched))
-        })
-        .collect();
+    let now = chrono::Local::now().naive_local();
+    let today = now.date();
+    let mut items: Vec<ListItem> = Vec::with_capacity(list_data.len());
+    let mut rows_for_item = Vec::with_capacity(list_data.len());
+    let mut current_header = None;
+    for h in list_data {
+        if grouped {
+            let label = history_date_label(&h.last_watched, today);
+            if current_header.as_ref() != Some(&label) {
+                items.push(ListItem::new(format!("── {} ──", label))
+                    .style(Style::default().fg(Color::DarkGray).add_modifier(Modifier::BOLD)));
+                current_header = Some(label);
+            }
+        }
+        rows_for_item.push(items.len());
+
+        let lib_mark = if ctx.lib_sessions.contains(h.anime.session.as_str()) { "❤ " } else { "  " };
+        let when = relative.then(|| relative_time(&h.last_watched, now)).flatten()
+            .unwrap_or_else(|| h.last_watched.clone());
+        let mut suffix = format!(" Ep {:<3} [{}]", h.last_episode, when);
+        if let Some(secs) = h.position_secs {
+            suffix.push_str(&format!(" @{}", format_timestamp(secs)));
+        }
+        if ctx.show_ids {
+            suffix.push_str(&format!(" [{} / {}]", h.anime.session, h.episode_session));
+        }
+        let max_title = title_width(layout[0], 2 + display_width(&suffix), ctx.title_cap);
+        let title = pad_str(&truncate_str(&h.anime.title, max_title), max_title);
+        items.push(ListItem::new(format!("{}{}{}", lib_mark, title, suffix)));
+    }
 
+    let title = format!(" History {}{}", if grouped { "(by date) " } else { "" }, ctx.filter);
     let list = List::new(items)
-        .block(Block::default().borders(Borders::ALL).title(" History ").border_style(Style::default().fg(Color::Cyan)))
+        .block(Block::default().borders(Borders::ALL).title(title).border_style(Style::default().fg(ctx.accent)))
         .highlight_style(Style::default().add_modifier(Modifier::BOLD).fg(Color::Yellow))
         .highlight_symbol("▶ ");
 
-    f.render_stateful_widget(list, layout[0], state);
+    let mut view = ListState::default()
+        .with_offset(state.offset())
+        .with_selected(state.selected().and_then(|i| rows_for_item.get(i).copied()));
+    f.render_stateful_widget(list, layout[0], &mut view);
+    *state.offset_mut() = view.offset();
 
     if let Some(i) = state.selected() {
         if let Some(item) = list_data.get(i) {
-            render_details(f, layout[1], &item.anime, lib_sessions);
+            render_details(f, layout[1], &item.anime, ctx);
         }
     }
 }
 
-fn render_details(f: &mut Frame, area: Rect, anime: &Anime, lib_sessions: &HashSet<&str>) {
-    let is_lib = lib_sessions.contains(anime.session.as_str());
-    let details = format!(
-        "Title: {}\n\nType: {}\nStatus: {}\nEpisodes: {}\nScore: {}\nYear: {}\n\n{}",
+/// Full-screen details: what search gave us, plus `method=info` fields when the backend has
+/// them, falling back to Jikan metadata for genres and synopsis.
+fn render_info(f: &mut Frame, area: Rect, anime: &Anime, info: Option<&AnimeInfo>, scroll: u16, ctx: &ListCtx) {
+    let meta = ctx.metadata.get(&anime.session).and_then(|m| m.as_ref());
+    let unknown = || "Unknown".to_string();
+    let mut text = format!(
+        "{}\n\nType: {}\nStatus: {}\nEpisodes: {}\nScore: {}\nYear: {}",
+        anime.title,
+        anime.anime_type.as_deref().unwrap_or("Unknown"),
+        anime.status,
+        anime.episodes.map(|e| e.to_string()).unwrap_or_else(unknown),
+        anime.score.or_else(|| meta.and_then(|m| m.score)).map(|s| s.to_string()).unwrap_or_else(|| "N/A".to_string()),
+        anime.year.map(|y| y.to_string()).unwrap_or_else(unknown),
+    );
+    if let Some(studio) = info.and_then(|i| i.studio.as_deref()) {
+        text.push_str(&format!("\nStudio: {}", studio));
+    }
+    let genres = info.map(|i| &i.genres).filter(|g| !g.is_empty())
+        .or_else(|| meta.map(|m| &m.genres).filter(|g| !g.is_empty()));
+    if let Some(genres) = genres {
+        text.push_str(&format!("\nGenres: {}", genres.join(", ")));
+    }
+    let synopsis = info.and_then(|i| i.synopsis.as_deref())
+        .or_else(|| meta.and_then(|m| m.synopsis.as_deref()))
+        .unwrap_or("No synopsis available.");
+    text.push_str(&format!("\n\n{}", synopsis));
+
+    let paragraph = Paragraph::new(text)
+        .block(Block::default().borders(Borders::ALL).title(" Details ").border_style(Style::default().fg(ctx.accent)))
+        .wrap(Wrap { trim: true })
+        .scroll((scroll, 0))
+        .style(Style::default().fg(Color::White));
+    f.render_widget(paragraph, area);
+}
+
+fn render_details(f: &mut Frame, area: Rect, anime: &Anime, ctx: &ListCtx) {
+    let is_lib = ctx.lib_sessions.contains(anime.session.as_str());
+    let meta = ctx.metadata.get(&anime.session).and_then(|m| m.as_ref());
+    let progress = match (ctx.progress.get(anime.session.as_str()).copied().unwrap_or(0), anime.episodes) {
+        (0, _) => String::new(),
+        (watched, Some(total)) if total > 0 => {
+            let watched = watched.min(total);
+            format!("\nProgress: {}/{} ({}%)", watched, total, watched * 100 / total)
+        }
+        (watched, _) => format!("\nProgress: Ep {} watched", watched),
+    };
+    let mut details = format!(
+        "Title: {}\n\nType: {}\nStatus: {}\nEpisodes: {}\nScore: {}\nYear: {}{}\n\n{}",
         anime.title,
         anime.anime_type.as_deref().unwrap_or("Unknown"),
         anime.status,
         anime.episodes.map(|e| e.to_string()).unwrap_or_else(|| "Unknown".to_string()),
-        anime.score.map(|s| s.to_string()).unwrap_or_else(|| "N/A".to_string()),
+        anime.score.or_else(|| meta.and_then(|m| m.score)).map(|s| s.to_string()).unwrap_or_else(|| "N/A".to_string()),
         anime.year.map(|y| y.to_string()).unwrap_or_else(|| "Unknown".to_string()),
+        progress,
         if is_lib { "[ In Library ❤ ]" } else { "[ Press 'f' to add to library ]" }
     );
+    if let Some(meta) = meta {
+        if !meta.genres.is_empty() {
+            details.push_str(&format!("\n\nGenres: {}", meta.genres.join(", ")));
+        }
+        if let Some(synopsis) = &meta.synopsis {
+            details.push_str(&format!("\n\n{}", synopsis));
+        }
+    }
     let details_p = Paragraph::new(details)
         .block(Block::default().borders(Borders::ALL).title(" Details ").border_style(Style::default().fg(Color::Gray)))
         .wrap(Wrap { trim: true })
         .style(Style::default().fg(Color::White));
     f.render_widget(details_p, area);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncates_wide_titles_by_column() {
+        let title = "魔法少女まどか☆マギカ";
+        for width in 0..=display_width(title) + 2 {
+            let cut = truncate_str(title, width);
+            assert!(display_width(&cut) <= width, "{:?} is wider than {}", cut, width);
+        }
+        assert_eq!(truncate_str(title, 10), "魔法少...");
+        assert_eq!(truncate_str(title, 11), "魔法少女...");
+        assert_eq!(truncate_str(title, 40), title);
+    }
+
+    /// An `App` on the scratch directories [`config::platform_dir`] hands out in tests.
+    fn test_app() -> App {
+        App::new().unwrap()
+    }
+
+    #[tokio::test]
+    async fn upcoming_shows_stay_on_search_results() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            use std::io::{Read, Write};
+            let (mut stream, _) = listener.accept().unwrap();
+            let _ = stream.read(&mut [0; 4096]);
+            let body = r#"{"title":"Upcoming","episodes":[],"total_pages":0,"page":1,"next":false}"#;
+            let _ = write!(stream, "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body);
+        });
+
+        let mut app = test_app();
+        let backend = BackendConfig { base_url: Some(format!("http://{}/api", addr)), requests_per_sec: Some(0.0), ..Default::default() };
+        app.client = AnimeClient::new(&backend).unwrap();
+        app.navigate(CurrentScreen::SearchResults);
+        app.selected_anime = Some(Anime {
+            id: 1, title: "Upcoming".to_string(), session: "upcoming".to_string(), episodes: None,
+            score: None, status: "Not yet aired".to_string(), year: None, anime_type: None,
+        });
+        let mut terminal = Terminal::new(ratatui::backend::TestBackend::new(80, 24)).unwrap();
+        app.load_episodes(&mut terminal, 1).await;
+
+        assert_eq!(app.current_screen, CurrentScreen::SearchResults);
+        assert!(app.status_message.contains("No episodes available yet"), "{}", app.status_message);
+        assert!(app.error_popup.is_none());
+    }
+
+    #[test]
+    fn status_log_keeps_messages_replaced_before_a_redraw() {
+        let mut app = test_app();
+        app.set_status("Fetching episodes...".to_string());
+        app.set_status("Page 1/1".to_string());
+        app.set_status("Page 1/1".to_string());
+        let log: Vec<&str> = app.status_log.iter().rev().take(2).map(|(_, m)| m.as_str()).collect();
+        assert_eq!(log, vec!["Page 1/1", "Fetching episodes..."]);
+    }
+
+    #[test]
+    fn back_retraces_each_step_and_revisits_unwind() {
+        let mut app = test_app();
+        for screen in [CurrentScreen::SearchResults, CurrentScreen::EpisodeList, CurrentScreen::QualitySelection] {
+            app.navigate(screen);
+        }
+        assert_eq!(app.screen_stack, [CurrentScreen::Search, CurrentScreen::SearchResults, CurrentScreen::EpisodeList]);
+        for expected in [CurrentScreen::EpisodeList, CurrentScreen::SearchResults, CurrentScreen::Search] {
+            app.go_back();
+            assert_eq!(app.current_screen, expected);
+        }
+        assert!(app.screen_stack.is_empty());
+
+        for screen in [CurrentScreen::SearchResults, CurrentScreen::EpisodeList, CurrentScreen::QualitySelection] {
+            app.navigate(screen);
+        }
+        app.navigate(CurrentScreen::SearchResults);
+        assert_eq!(app.screen_stack, [CurrentScreen::Search]);
+        app.go_back();
+        assert_eq!(app.current_screen, CurrentScreen::Search);
+    }
+
+    #[test]
+    fn resume_position_kept_without_history_entry() {
+        let mut app = test_app();
+        app.set_position("ep-resume-test", Some(754));
+        assert_eq!(app.saved_position("ep-resume-test"), Some(754));
+        app.record_history(anime_from_series("resume-test".to_string(), "Resume Test".to_string()), "ep-resume-test".to_string(), "3".to_string());
+        assert_eq!(app.history[0].position_secs, Some(754));
+        assert!(!app.positions.contains_key("ep-resume-test"));
+        app.set_position("ep-resume-test", None);
+        assert_eq!(app.saved_position("ep-resume-test"), None);
+    }
+
+    #[test]
+    fn franchise_keys_never_empty() {
+        assert_eq!(franchise_key("Mob Psycho 100 II"), franchise_key("Mob Psycho 100"));
+        assert_eq!(franchise_key("Attack on Titan Season 3 Part 2"), "attack on titan");
+        assert_eq!(franchise_key("86"), "86");
+        assert_ne!(franchise_key("86"), franchise_key("Movie"));
+        assert_ne!(franchise_key(": Prologue"), franchise_key("Movie"));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn tools_found_without_running_them() {
+        assert!(tool_available("sh"));
+        assert!(tool_available("/bin/sh"));
+        assert!(!tool_available("enuma-no-such-player"));
+        assert!(!tool_available("/nonexistent/mpv"));
+    }
+
+    #[test]
+    fn dashboard_counts_library_and_history() {
+        let anime = |title: &str| anime_from_series(title.to_lowercase(), title.to_string());
+        let watched = |title: &str, ep: &str| HistoryItem {
+            anime: anime(title),
+            episode_session: String::new(),
+            last_episode: ep.to_string(),
+            last_watched: String::new(),
+            position_secs: None,
+        };
+        assert!(dashboard(&[], &[]).starts_with("Nothing here yet"));
+        assert_eq!(dashboard(&[anime("Frieren")], &[]), "Library: 1 show · History: 0 watched");
+        assert_eq!(
+            dashboard(&[anime("Frieren"), anime("Mushishi")], &[watched("Frieren", "5"), watched("Mushishi", "12")]),
+            "Library: 2 shows · History: 2 watched · Last: Frieren Ep 5",
+        );
+    }
+
+    #[test]
+    fn dedup_keeps_first_of_each_session() {
+        let mut items = vec![("a", "1".to_string()), ("b", "2".to_string()), ("c", "1".to_string())];
+        assert!(dedup_by_session(&mut items, |i| &i.1));
+        assert_eq!(items.iter().map(|i| i.0).collect::<Vec<_>>(), ["a", "b"]);
+        assert!(!dedup_by_session(&mut items, |i| &i.1));
+
+        let mut library = vec![
+            anime_from_series("frieren".to_string(), "Frieren".to_string()),
+            anime_from_series("bocchi".to_string(), "Bocchi the Rock!".to_string()),
+            anime_from_series("frieren".to_string(), "Sousou no Frieren".to_string()),
+        ];
+        assert!(dedup_by_session(&mut library, |a| &a.session));
+        assert_eq!(library.iter().map(|a| a.title.as_str()).collect::<Vec<_>>(), ["Frieren", "Bocchi the Rock!"]);
+    }
+
+    #[test]
+    fn backups_never_replace_older_ones() {
+        let dir = std::env::temp_dir().join(format!("enuma-backup-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("library.json");
+        assert_eq!(backup_path(&file), dir.join("library.json.bak"));
+        std::fs::write(dir.join("library.json.bak"), "[]").unwrap();
+        assert_eq!(backup_path(&file), dir.join("library.json.bak.1"));
+        std::fs::write(dir.join("library.json.bak.1"), "[]").unwrap();
+        assert_eq!(backup_path(&file), dir.join("library.json.bak.2"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn only_unparseable_files_are_backed_up() {
+        let _app = test_app();
+        std::fs::create_dir_all(data_dir()).unwrap();
+        let mut recovered = Vec::new();
+
+        let broken = data_dir().join("broken-test.json");
+        std::fs::write(&broken, "{not json").unwrap();
+        let data: Vec<String> = App::load_or_recover("broken-test.json", &mut recovered);
+        assert!(data.is_empty());
+        assert!(!broken.exists());
+        assert!(data_dir().join("broken-test.json.bak").is_file());
+
+        // A directory can't be read as a file, which says nothing about its contents.
+        let unreadable = data_dir().join("unreadable-test.json");
+        std::fs::create_dir_all(&unreadable).unwrap();
+        let _: Vec<String> = App::load_or_recover("unreadable-test.json", &mut recovered);
+        assert!(unreadable.is_dir());
+        assert!(!data_dir().join("unreadable-test.json.bak").exists());
+        assert_eq!(recovered.len(), 2);
+    }
+
+    #[test]
+    fn concurrent_saves_leave_one_whole_file() {
+        let dir = std::env::temp_dir().join(format!("enuma-save-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("library.json");
+        let payloads: Vec<Vec<String>> = vec![vec!["a".repeat(4096); 64], vec!["b".repeat(4096); 32]];
+        for _ in 0..20 {
+            std::thread::scope(|scope| {
+                for payload in &payloads {
+                    let path = &path;
+                    scope.spawn(move || write_json_atomic(path, payload).unwrap());
+                }
+            });
+            let saved: Vec<String> = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+            assert!(payloads.contains(&saved));
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn offline_files_match_download_names() {
+        let dir = std::path::Path::new("videos");
+        assert_eq!(
+            episode_path(dir, "Re:Zero", "5", "mp4"),
+            dir.join("Re_Zero").join("Re_Zero - E05.mp4"),
+        );
+        assert_eq!(
+            episode_path(dir, "Frieren", "13.5", "mp4"),
+            dir.join("Frieren").join("Frieren - E13.5.mp4"),
+        );
+    }
+
+    #[test]
+    fn search_pages_cache_separately() {
+        assert_eq!(search_cache_key("  Frieren ", 1), "frieren");
+        assert_eq!(search_cache_key("frieren", 1), search_cache_key("FRIEREN", 1));
+        assert_ne!(search_cache_key("frieren", 2), search_cache_key("frieren", 1));
+        assert_ne!(search_cache_key("frieren", 2), search_cache_key("frieren", 3));
+    }
+
+    #[test]
+    fn per_anime_args_follow_global_ones() {
+        let config = Config {
+            mpv_extra_args: vec!["--sub-delay=0".to_string()],
+            per_anime_args: vec![("frieren".to_string(), vec!["--sub-delay=1.5".to_string(), "--aid=2".to_string()])]
+                .into_iter().collect(),
+            ..Default::default()
+        };
+        let vars = |anime_session| PlayerVars {
+            url: "https://example.com/uwu.m3u8",
+            title: "Frieren",
+            referrer: "https://kwik.cx/",
+            watch_later: "wl",
+            start: None,
+            anime_session,
+            duration_script: None,
+        };
+        let args = config.player_args(&vars(Some("frieren"))).unwrap();
+        let tail: Vec<&str> = args.iter().rev().take(4).rev().map(String::as_str).collect();
+        assert_eq!(tail, ["--sub-delay=0", "--sub-delay=1.5", "--aid=2", "https://example.com/uwu.m3u8"]);
+        let other = config.player_args(&vars(Some("bocchi"))).unwrap();
+        assert!(!other.iter().any(|a| a == "--aid=2"));
+        assert!(config.player_args(&vars(None)).unwrap().contains(&"--sub-delay=0".to_string()));
+    }
+
+    #[test]
+    fn watched_past_threshold_or_at_the_end() {
+        let saved = |position_secs, duration_secs| SavedPlayback { position_secs, duration_secs };
+        let (short, long) = (Duration::from_secs(5), Duration::from_secs(600));
+        assert!(counts_as_watched(&saved(Some(1250), Some(1440.0)), true, long, 0.85));
+        assert!(!counts_as_watched(&saved(Some(600), Some(1440.0)), true, long, 0.85));
+        assert!(counts_as_watched(&saved(Some(1250), Some(1440.0)), false, short, 0.85));
+        assert!(counts_as_watched(&saved(None, Some(1440.0)), true, short, 0.85));
+        assert!(!counts_as_watched(&saved(None, Some(1440.0)), false, long, 0.85));
+        assert!(counts_as_watched(&saved(Some(30), None), true, long, 0.85));
+        assert!(!counts_as_watched(&saved(None, None), true, short, 0.85));
+    }
+
+    #[test]
+    fn saved_playback_reads_resume_and_duration_files() {
+        let dir = std::env::temp_dir().join(format!("enuma-test-playback-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("0A1B2C"), "# https://example.com/uwu.m3u8\nstart=1234.567000\n").unwrap();
+        std::fs::write(dir.join("duration"), "duration=1440.000000\n").unwrap();
+        let saved = take_saved_playback(&dir);
+        assert_eq!(saved.position_secs, Some(1234));
+        assert_eq!(saved.duration_secs, Some(1440.0));
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+        let _ = std::fs::remove_dir(&dir);
+    }
+
+    #[test]
+    fn indeterminate_bar_bounces_within_track() {
+        let widths: Vec<usize> = (0..40).map(|tick| indeterminate_bar(20, tick).chars().count()).collect();
+        assert!(widths.iter().all(|&w| w == 20));
+        assert_eq!(indeterminate_bar(20, 0), format!("{}{}", "█".repeat(5), "░".repeat(15)));
+        assert_eq!(indeterminate_bar(20, 15), format!("{}{}", "░".repeat(15), "█".repeat(5)));
+        assert_eq!(indeterminate_bar(20, 16), indeterminate_bar(20, 14));
+        assert_eq!(indeterminate_bar(1, 7), "█");
+        assert_eq!(indeterminate_bar(0, 3), "");
+    }
+
+    #[test]
+    fn download_fraction_counts_finished_batch_items() {
+        let item = |status| BatchItem { ep: String::new(), path: PathBuf::new(), status };
+        let mut downloads = HashMap::new();
+        assert_eq!(download_fraction(&downloads, &[]), None);
+
+        let batch = [
+            item(BatchStatus::Done),
+            item(BatchStatus::Active(Transfer { done: 30.0, total: Some(60.0) })),
+            item(BatchStatus::Queued),
+            item(BatchStatus::Queued),
+        ];
+        assert_eq!(download_fraction(&downloads, &batch), Some(Some(0.375)));
+
+        downloads.insert(PathBuf::from("a.mp4"), Transfer::default());
+        assert_eq!(download_fraction(&downloads, &batch), Some(None));
+        downloads.insert(PathBuf::from("a.mp4"), Transfer { done: 90.0, total: Some(60.0) });
+        assert_eq!(download_fraction(&downloads, &batch), Some(Some(0.6875)));
+    }
+
+    #[test]
+    fn next_server_skips_failed_streams() {
+        let stream = |name: &str, link: &str| StreamItem { name: name.to_string(), link: link.to_string() };
+        let streams = [
+            stream("SubsPlease · 1080p", "a"),
+            stream("SubsPlease · 720p", "b"),
+            stream("Judas · 1080p", "c"),
+            stream("Judas · 720p", "d"),
+        ];
+        let mut failed: HashSet<String> = vec!["a".to_string()].into_iter().collect();
+        assert_eq!(next_untried(&streams, &failed, 0, true), Some(2));
+        assert_eq!(next_untried(&streams, &failed, 0, false), Some(1));
+        failed.insert("c".to_string());
+        assert_eq!(next_untried(&streams, &failed, 2, true), None);
+        assert_eq!(next_untried(&streams, &failed, 2, false), Some(3));
+        assert_eq!(next_untried(&streams, &failed, 3, false), Some(1));
+        assert_eq!(next_untried(&[], &failed, 0, false), None);
+    }
+
+    #[test]
+    fn episode_ranges_and_lists() {
+        assert_eq!(parse_episode_numbers("5-8").unwrap(), [5, 6, 7, 8]);
+        assert_eq!(parse_episode_numbers("9, 5,7").unwrap(), [5, 7, 9]);
+        assert_eq!(parse_episode_numbers(" 1-3,2-4 ,10,").unwrap(), [1, 2, 3, 4, 10]);
+        assert_eq!(parse_episode_numbers("12").unwrap(), [12]);
+        for bad in ["", " , ", "5-", "-5", "a-3", "12-5", "1.5", "5-7-9", "1-100000"] {
+            assert!(parse_episode_numbers(bad).is_err(), "{:?} should be rejected", bad);
+        }
+    }
+
+    #[test]
+    fn pasted_kwik_links() {
+        for (input, expected) in [
+            ("https://kwik.cx/f/AbC123", "AbC123"),
+            ("  https://kwik.si/f/Xy9?x=1 ", "Xy9"),
+            ("kwik.cx/f/xyz", "xyz"),
+            ("https://kwik.si/e/a_b-C9", "a_b-C9"),
+        ] {
+            match parse_pasted_url(input) {
+                Ok(PastedUrl::Kwik { url, id }) => {
+                    assert_eq!(id, expected);
+                    assert_eq!(url, input.trim());
+                }
+                _ => panic!("{} not parsed as a kwik link", input),
+            }
+        }
+        assert!(parse_pasted_url("https://kwik.cx/").is_err());
+    }
+
+    #[test]
+    fn pasted_anime_pages() {
+        match parse_pasted_url("https://animepahe.si/anime/1b2c3d4e-aaaa-bbbb?ref=home") {
+            Ok(PastedUrl::Anime(session)) => assert_eq!(session, "1b2c3d4e-aaaa-bbbb"),
+            _ => panic!("anime page not recognised"),
+        }
+        assert!(parse_pasted_url("https://example.com/watch").is_err());
+        assert!(parse_pasted_url("").is_err());
+    }
+
+    #[test]
+    fn next_unwatched_skips_gaps_and_history() {
+        let episodes: Vec<Episode> = ["1", "2", "3", "4", "5"].iter().map(|n| Episode {
+            episode: n.to_string(), session: format!("ep{}", n), snapshot: String::new(), title: None, created_at: None,
+        }).collect();
+        let watched: HashSet<String> = ["ep1", "ep2", "ep4"].iter().map(|s| s.to_string()).collect();
+        // Watched out of order: 3 is the gap, not 4 (watched count + 1).
+        assert_eq!(first_unwatched(&episodes, &watched, 0.0), Some(2));
+        // History already reached Ep 3, so the next one past it that's unwatched is 5.
+        assert_eq!(first_unwatched(&episodes, &watched, 3.0), Some(4));
+        assert_eq!(first_unwatched(&episodes, &watched, 5.0), None);
+    }
+
+    #[test]
+    fn offline_filter_keeps_finished_downloads() {
+        let mut app = test_app();
+        let dir = std::env::temp_dir().join(format!("enuma-offline-test-{}", std::process::id()));
+        app.config.download_dir = Some(dir.to_string_lossy().into_owned());
+        app.selected_anime = Some(Anime {
+            id: 1, title: "Show".to_string(), session: "show".to_string(), episodes: None,
+            score: None, status: String::new(), year: None, anime_type: None,
+        });
+        app.page_episodes = ["1", "2"].iter().map(|n| Episode {
+            episode: n.to_string(), session: format!("ep{}", n), snapshot: String::new(), title: None, created_at: None,
+        }).collect();
+        let done = episode_path(&dir, "Show", "2", "mp4");
+        std::fs::create_dir_all(done.parent().unwrap()).unwrap();
+        std::fs::write(&done, b"").unwrap();
+        // An unfinished download only exists as a .part file.
+        std::fs::write(episode_path(&dir, "Show", "1", "mp4.part"), b"").unwrap();
+
+        app.toggle_offline();
+        let shown: Vec<&str> = app.episode_list.iter().map(|ep| ep.episode.as_str()).collect();
+        assert_eq!(shown, vec!["2"]);
+        app.toggle_offline();
+        assert_eq!(app.episode_list.len(), 2);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn empty_exact_pages_point_to_the_next_one() {
+        let mut app = test_app();
+        app.search_mode = SearchMode::Exact;
+        app.search_query = "Frieren".to_string();
+        let other = Anime {
+            id: 1, title: "Something Else".to_string(), session: "else".to_string(), episodes: None,
+            score: None, status: String::new(), year: None, anime_type: None,
+        };
+        app.show_search_results(SearchResponse { data: vec![other.clone()], last_page: 3, current_page: 1 }, false);
+        assert!(app.search_results.is_empty());
+        assert!(app.status_message.contains("Press Right"), "{}", app.status_message);
+
+        app.show_search_results(SearchResponse { data: vec![other], last_page: 3, current_page: 3 }, false);
+        assert!(!app.status_message.contains("Press Right"), "{}", app.status_message);
+    }
+
+    #[test]
+    fn queries_are_trimmed_and_collapsed() {
+        assert_eq!(tidy_query("  fate/stay   night \t"), "fate/stay night");
+        assert_eq!(tidy_query("a &  b"), "a & b");
+        assert_eq!(tidy_query("   "), "");
+    }
+
+    #[test]
+    fn direct_search_prefixes() {
+        assert_eq!(parse_direct_ref("id:4321").unwrap().unwrap(), DirectRef::Id(4321));
+        assert_eq!(parse_direct_ref("ID: 7").unwrap().unwrap(), DirectRef::Id(7));
+        assert_eq!(
+            parse_direct_ref("session:1b2c3d4e-aaaa-bbbb").unwrap().unwrap(),
+            DirectRef::Session("1b2c3d4e-aaaa-bbbb".to_string()),
+        );
+        assert_eq!(
+            parse_direct_ref("Session: https://animepahe.si/anime/1b2c3d4e-aaaa-bbbb").unwrap().unwrap(),
+            DirectRef::Session("1b2c3d4e-aaaa-bbbb".to_string()),
+        );
+        assert!(parse_direct_ref("ID:INVADED").is_none());
+        assert!(parse_direct_ref("session:short").unwrap().is_err());
+        assert!(parse_direct_ref("Re:Zero").is_none());
+        assert!(parse_direct_ref("frieren").is_none());
+    }
+}
@@ -0,0 +1,38 @@
+use crate::api::Anime;
+use std::collections::HashMap;
+
+/// Renders `library` in MyAnimeList's legacy XML export format, which MAL's importer
+/// accepts. We have no MAL ids, so every entry is id 0 and MAL matches on the title.
+/// `progress` maps an anime session to episodes watched and decides each entry's status.
+pub fn export_xml(library: &[Anime], progress: &HashMap<&str, u32>) -> String {
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\" ?>\n<myanimelist>\n");
+    xml.push_str("  <myinfo>\n    <user_export_type>1</user_export_type>\n");
+    xml.push_str(&format!("    <user_total_anime>{}</user_total_anime>\n  </myinfo>\n", library.len()));
+
+    for anime in library {
+        let total = anime.episodes.unwrap_or(0);
+        let watched = progress.get(anime.session.as_str()).copied().unwrap_or(0);
+        let watched = if total > 0 { watched.min(total) } else { watched };
+        let status = match watched {
+            0 => "Plan to Watch",
+            w if total > 0 && w >= total => "Completed",
+            _ => "Watching",
+        };
+        xml.push_str("  <anime>\n");
+        xml.push_str("    <series_animedb_id>0</series_animedb_id>\n");
+        xml.push_str(&format!("    <series_title>{}</series_title>\n", cdata(&anime.title)));
+        xml.push_str(&format!("    <series_type>{}</series_type>\n", cdata(anime.anime_type.as_deref().unwrap_or("Unknown"))));
+        xml.push_str(&format!("    <series_episodes>{}</series_episodes>\n", total));
+        xml.push_str(&format!("    <my_watched_episodes>{}</my_watched_episodes>\n", watched));
+        xml.push_str(&format!("    <my_status>{}</my_status>\n", status));
+        xml.push_str("    <update_on_import>1</update_on_import>\n");
+        xml.push_str("  </anime>\n");
+    }
+    xml.push_str("</myanimelist>\n");
+    xml
+}
+
+/// Wraps `text` in CDATA, splitting any `]]>` so it can't end the section early.
+fn cdata(text: &str) -> String {
+    format!("<![CDATA[{}]]>", text.replace("]]>", "]]]]><![CDATA[>"))
+}
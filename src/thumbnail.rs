@@ -0,0 +1,89 @@
+use anyhow::{bail, Context, Result};
+use image::DynamicImage;
+use ratatui_image::picker::{Picker, ProtocolType};
+use std::process::Stdio;
+use tokio::process::Command;
+
+const RAMP: &[u8] = b" .:-=+*#%@";
+
+/// Width snapshots are scaled to before being handed to the terminal.
+const IMAGE_WIDTH: u32 = 480;
+
+/// The terminal's image protocol, asked of the terminal itself. Must run after entering the
+/// alternate screen and before reading any events, since the replies arrive on stdin.
+/// `None` when the terminal has no Kitty, iTerm2 or Sixel support, as half-block "images"
+/// are no improvement on `ascii_thumbnails`.
+pub fn detect_picker() -> Option<Picker> {
+    Picker::from_query_stdio().ok()
+        .filter(|picker| picker.protocol_type() != ProtocolType::Halfblocks)
+}
+
+/// Fetches and decodes the image at `url`, scaled down for previews.
+pub async fn preview_image(url: &str) -> Result<DynamicImage> {
+    let output = Command::new("ffmpeg")
+        .args(["-loglevel", "error", "-headers", "Referer: https://kwik.cx/\r\n", "-i", url])
+        .args(["-frames:v", "1", "-vf"])
+        .arg(format!("scale='min({},iw)':-2", IMAGE_WIDTH))
+        .args(["-c:v", "png", "-f", "image2pipe", "-"])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .await
+        .context("Failed to run ffmpeg. Is it installed?")?;
+
+    if !output.status.success() {
+        bail!("ffmpeg could not decode thumbnail");
+    }
+    image::load_from_memory_with_format(&output.stdout, image::ImageFormat::Png)
+        .context("ffmpeg returned an unreadable thumbnail")
+}
+
+/// Renders the image at `url` as `cols`x`rows` characters by mapping luminance onto a ramp.
+/// Decoding is delegated to ffmpeg, which emits a greyscale PGM we can read directly.
+pub async fn ascii_art(url: &str, cols: u16, rows: u16) -> Result<String> {
+    let output = Command::new("ffmpeg")
+        .args(["-loglevel", "error", "-headers", "Referer: https://kwik.cx/\r\n", "-i", url])
+        .args(["-frames:v", "1", "-vf"])
+        .arg(format!("scale={}:{},format=gray", cols.max(1), rows.max(1)))
+        .args(["-c:v", "pgm", "-f", "image2pipe", "-"])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .await
+        .context("Failed to run ffmpeg. Is it installed?")?;
+
+    if !output.status.success() {
+        bail!("ffmpeg could not decode thumbnail");
+    }
+    let (width, height, pixels) = parse_pgm(&output.stdout)?;
+
+    let mut art = String::with_capacity((width + 1) * height);
+    for row in pixels.chunks(width).take(height) {
+        for &luma in row {
+            let idx = luma as usize * (RAMP.len() - 1) / 255;
+            art.push(RAMP[idx] as char);
+        }
+        art.push('\n');
+    }
+    Ok(art)
+}
+
+/// Parses a binary (P5) PGM with 8-bit samples into `(width, height, pixels)`.
+fn parse_pgm(data: &[u8]) -> Result<(usize, usize, &[u8])> {
+    let mut fields = Vec::with_capacity(4);
+    let mut pos = 0;
+    while fields.len() < 4 {
+        while pos < data.len() && data[pos].is_ascii_whitespace() { pos += 1; }
+        let start = pos;
+        while pos < data.len() && !data[pos].is_ascii_whitespace() { pos += 1; }
+        if start == pos { bail!("Truncated PGM header"); }
+        fields.push(std::str::from_utf8(&data[start..pos])?);
+    }
+    pos += 1;
+
+    if fields[0] != "P5" { bail!("Unexpected image format {}", fields[0]); }
+    let width: usize = fields[1].parse()?;
+    let height: usize = fields[2].parse()?;
+    let pixels = data.get(pos..pos + width * height).context("Truncated PGM data")?;
+    Ok((width, height, pixels))
+}